@@ -1,12 +1,92 @@
 use wasm_bindgen::prelude::*;
-use js_sys::Reflect;
+use wasm_bindgen::JsCast;
+use js_sys::{Reflect, Function};
 use crate::i18n::I18n;
+use crate::emulator::{
+    Device, Pacing, PLAYERS, BUTTON_A, BUTTON_B, BUTTON_SELECT, BUTTON_START,
+    BUTTON_UP, BUTTON_DOWN, BUTTON_LEFT, BUTTON_RIGHT
+};
 
 const DEFAULT_LOCALE: &str = "en";
 
+// The names of the buttons in the key map configuration
+const BUTTON_NAMES: [(&str, u8); 8] = [
+    ("a", BUTTON_A), ("b", BUTTON_B), ("select", BUTTON_SELECT), ("start", BUTTON_START),
+    ("up", BUTTON_UP), ("down", BUTTON_DOWN), ("left", BUTTON_LEFT), ("right", BUTTON_RIGHT)
+];
+
+// The default KeyboardEvent.code of the buttons of player 1 and 2, in the order of BUTTON_NAMES
+const DEFAULT_KEYS: [[&str; 8]; 2] = [
+    ["KeyK", "KeyJ", "KeyU", "KeyI", "KeyW", "KeyS", "KeyA", "KeyD"],
+    ["Numpad2", "Numpad1", "Numpad4", "Numpad5", "ArrowUp", "ArrowDown", "ArrowLeft", "ArrowRight"]
+];
+
+// The default keys of the mat buttons, from left to right and top to bottom as the player sees.
+// They're on the number row, apart from the buttons of player 1.
+const DEFAULT_MAT_KEYS: [&str; 12] = [
+    "Digit1", "Digit2", "Digit3", "Digit4",
+    "Digit5", "Digit6", "Digit7", "Digit8",
+    "Digit9", "Digit0", "Minus", "Equal"
+];
+
+// The default key of the microphone on the Famicom controller 2
+const DEFAULT_MIC_KEY: &str = "KeyM";
+
+// The keys of the Family BASIC keyboard in the rows of its matrix.
+// Column 0 is reported in the lower 4 bits and column 1 in the upper 4 bits.
+const FAMILY_KEYBOARD: [[&str; 8]; 9] = [
+    ["BracketRight", "BracketLeft", "Enter", "F8", "Pause", "IntlYen", "ShiftRight", "KanaMode"],
+    ["Semicolon", "Quote", "Backquote", "F7", "Equal", "Minus", "Slash", "IntlRo"],
+    ["KeyK", "KeyL", "KeyO", "F6", "Digit0", "KeyP", "Comma", "Period"],
+    ["KeyJ", "KeyU", "KeyI", "F5", "Digit8", "Digit9", "KeyN", "KeyM"],
+    ["KeyH", "KeyG", "KeyY", "F4", "Digit6", "Digit7", "KeyV", "KeyB"],
+    ["KeyD", "KeyR", "KeyT", "F3", "Digit4", "Digit5", "KeyC", "KeyF"],
+    ["KeyA", "KeyS", "KeyW", "F2", "Digit3", "KeyE", "KeyZ", "KeyX"],
+    ["ControlLeft", "KeyQ", "Escape", "F1", "Digit2", "Digit1", "AltLeft", "ShiftLeft"],
+    ["ArrowLeft", "ArrowRight", "ArrowUp", "Home", "Insert", "Backspace", "Space", "ArrowDown"]
+];
+
+// The names of the devices in the configuration, the device is decided by the cartridge if absent
+const DEVICE_NAMES: [(&str, Device); 11] = [
+    ("standard", Device::Standard),
+    ("fourScore", Device::FourScore),
+    ("famicom4P", Device::Famicom4P),
+    ("zapper", Device::Zapper),
+    ("vaus", Device::Vaus),
+    ("vausFamicom", Device::VausFamicom),
+    ("powerPadA", Device::PowerPadA),
+    ("powerPadB", Device::PowerPadB),
+    ("familyTrainerA", Device::FamilyTrainerA),
+    ("familyTrainerB", Device::FamilyTrainerB),
+    ("familyKeyboard", Device::FamilyKeyboard)
+];
+
+// The names of the pacing modes in the configuration
+const PACING_NAMES: [(&str, Pacing); 3] = [
+    ("interval", Pacing::Interval),
+    ("animationFrame", Pacing::AnimationFrame),
+    ("audio", Pacing::Audio)
+];
+
+// The default gamepad buttons in the standard layout, in the order of BUTTON_NAMES
+const DEFAULT_GAMEPAD_BUTTONS: [u32; 8] = [1, 0, 8, 9, 12, 13, 14, 15];
+// The default axes of the left stick in the standard layout
+const DEFAULT_GAMEPAD_AXES: (u32, u32) = (0, 1);
+const DEFAULT_DEADZONE: f64 = 0.5;
+
 pub struct Configuration {
     i18n: I18n,
-    fps: Option<f64>
+    fps: Option<f64>,
+    on_save: Option<Function>,
+    keys: KeyMap,
+    gamepad: GamepadMap,
+    device: Option<Device>,
+    famicom: bool,
+    microphone: bool,
+    volume: f64,
+    mute: bool,
+    pacing: Pacing,
+    bus_conflicts: Option<bool>
 }
 
 impl Configuration {
@@ -24,7 +104,18 @@ impl Configuration {
 
         Self {
             i18n,
-            fps: get_conf_integer(conf, "fps")
+            fps: get_conf_integer(conf, "fps"),
+            on_save: get_conf_function(conf, "onSave"),
+            keys: KeyMap::new(conf),
+            gamepad: GamepadMap::new(conf),
+            device: get_conf_string(conf, "device").and_then(|d| find_device(d.as_str())),
+            famicom: get_conf_bool(conf, "famicom").unwrap_or(false),
+            microphone: get_conf_bool(conf, "microphone").unwrap_or(false),
+            volume: get_conf_integer(conf, "volume").map_or(1.0, |v| v.clamp(0.0, 1.0)),
+            mute: get_conf_bool(conf, "mute").unwrap_or(false),
+            pacing: get_conf_string(conf, "pacing").and_then(|p| find_pacing(p.as_str()))
+                .unwrap_or(Pacing::Interval),
+            bus_conflicts: get_conf_bool(conf, "busConflicts")
         }
     }
 
@@ -35,6 +126,207 @@ impl Configuration {
     pub fn fps(&self) -> Option<f64> {
         self.fps
     }
+
+    // Called with the battery RAM data when the game writes it
+    pub fn on_save(&self) -> Option<&Function> {
+        self.on_save.as_ref()
+    }
+
+    pub fn keys(&self) -> &KeyMap {
+        &self.keys
+    }
+
+    pub fn gamepad(&self) -> &GamepadMap {
+        &self.gamepad
+    }
+
+    // The input device, None to select from the cartridge
+    pub fn device(&self) -> Option<Device> {
+        self.device
+    }
+
+    // Famicom controllers, the controller 2 has a microphone instead of Select and Start
+    pub fn famicom(&self) -> bool {
+        self.famicom
+    }
+
+    // Whether the browser microphone drives the microphone of the Famicom controller 2
+    pub fn microphone(&self) -> bool {
+        self.famicom && self.microphone
+    }
+
+    // The audio volume of 0-1
+    pub fn volume(&self) -> f64 {
+        self.volume
+    }
+
+    // No audio is played if muted
+    pub fn mute(&self) -> bool {
+        self.mute
+    }
+
+    // How the frames are scheduled
+    pub fn pacing(&self) -> Pacing {
+        self.pacing
+    }
+
+    // Whether the boards emulate the bus conflicts, None to decide by the submapper
+    pub fn bus_conflicts(&self) -> Option<bool> {
+        self.bus_conflicts
+    }
+}
+
+// Maps the keys on the keyboard to the buttons of the players
+#[derive(Clone)]
+pub struct KeyMap {
+    // KeyboardEvent.code, player index and button
+    keys: Vec<(String, usize, u8)>,
+    // KeyboardEvent.code of the mat buttons, in the order of DEFAULT_MAT_KEYS
+    mat: Vec<String>,
+    mic: String
+}
+
+impl KeyMap {
+    // Buttons absent from the configuration keep the default keys
+    fn new(conf: &JsValue) -> Self {
+        let players = Reflect::get(conf, &JsValue::from_str("keys")).ok()
+            .filter(|k| k.is_object());
+        let mat = players.as_ref()
+            .and_then(|p| Reflect::get(p, &JsValue::from_str("mat")).ok())
+            .filter(|m| m.is_object());
+        Self::from_lookup(|player, name| {
+            let key = JsValue::from_str(format!("player{}", player + 1).as_str());
+            let buttons = Reflect::get(players.as_ref()?, &key).ok()?;
+            get_conf_string(&buttons, name)
+        }, |i| get_conf_string(mat.as_ref()?, i.to_string().as_str()),
+        players.as_ref().and_then(|p| get_conf_string(p, "mic")))
+    }
+
+    fn from_lookup<F, M>(lookup: F, mat_lookup: M, mic: Option<String>) -> Self
+        where F: Fn(usize, &str) -> Option<String>, M: Fn(usize) -> Option<String> {
+
+        let mut keys = Vec::new();
+        for player in 0..PLAYERS {
+            for (i, (name, button)) in BUTTON_NAMES.iter().enumerate() {
+                // Player 3 and 4 have no default keys
+                let code = lookup(player, name)
+                    .or_else(|| DEFAULT_KEYS.get(player).map(|d| d[i].to_string()));
+                if let Some(code) = code {
+                    keys.push((code, player, *button));
+                }
+            }
+        }
+        let mat = DEFAULT_MAT_KEYS.iter().enumerate()
+            .map(|(i, d)| mat_lookup(i).unwrap_or_else(|| d.to_string()))
+            .collect();
+        Self {
+            keys,
+            mat,
+            mic: mic.unwrap_or_else(|| DEFAULT_MIC_KEY.to_string())
+        }
+    }
+
+    // Returns the player index and the button mapped from the KeyboardEvent.code
+    pub fn find(&self, code: &str) -> Option<(usize, u8)> {
+        self.keys.iter()
+            .find(|(c, _, _)| c == code)
+            .map(|(_, player, button)| (*player, *button))
+    }
+
+    // Returns the position of the mat button mapped from the KeyboardEvent.code
+    pub fn find_mat(&self, code: &str) -> Option<usize> {
+        self.mat.iter().position(|c| c == code)
+    }
+
+    pub fn is_mic(&self, code: &str) -> bool {
+        self.mic == code
+    }
+
+    // Returns the row and the bit of the Family BASIC keyboard matrix
+    pub fn find_family_key(&self, code: &str) -> Option<(usize, u8)> {
+        FAMILY_KEYBOARD.iter().enumerate().find_map(|(row, keys)| {
+            keys.iter().position(|k| *k == code).map(|bit| (row, 1 << bit))
+        })
+    }
+}
+
+// Maps the buttons and the axes of the gamepads to the buttons of the controller
+#[derive(Clone)]
+pub struct GamepadMap {
+    // Gamepad button indexes, in the order of BUTTON_NAMES
+    buttons: [u32; 8],
+    // Horizontal and vertical axis indexes
+    axes: (u32, u32),
+    // Axis values within the deadzone are ignored
+    deadzone: f64
+}
+
+impl GamepadMap {
+    fn new(conf: &JsValue) -> Self {
+        let gamepad = Reflect::get(conf, &JsValue::from_str("gamepad")).ok()
+            .filter(|g| g.is_object());
+        let get = |group: &str, key: &str| {
+            let values = Reflect::get(gamepad.as_ref()?, &JsValue::from_str(group)).ok()?;
+            get_conf_integer(&values, key)
+        };
+        let mut map = Self::default();
+        for (i, (name, _)) in BUTTON_NAMES.iter().enumerate() {
+            if let Some(index) = get("buttons", name) {
+                map.buttons[i] = index as u32;
+            }
+        }
+        if let Some(index) = get("axes", "horizontal") {
+            map.axes.0 = index as u32;
+        }
+        if let Some(index) = get("axes", "vertical") {
+            map.axes.1 = index as u32;
+        }
+        if let Some(deadzone) = gamepad.as_ref().and_then(|g| get_conf_integer(g, "deadzone")) {
+            map.deadzone = deadzone.clamp(0.0, 1.0);
+        }
+        map
+    }
+
+    fn default() -> Self {
+        Self {
+            buttons: DEFAULT_GAMEPAD_BUTTONS,
+            axes: DEFAULT_GAMEPAD_AXES,
+            deadzone: DEFAULT_DEADZONE
+        }
+    }
+
+    // Returns the controller buttons from the states of the gamepad buttons and axes
+    pub fn buttons<P, A>(&self, pressed: P, axis: A) -> u8
+        where P: Fn(u32) -> bool, A: Fn(u32) -> f64 {
+
+        let mut buttons = 0;
+        for (i, (_, button)) in BUTTON_NAMES.iter().enumerate() {
+            if pressed(self.buttons[i]) {
+                buttons |= button;
+            }
+        }
+        let x = axis(self.axes.0);
+        let y = axis(self.axes.1);
+        if x < -self.deadzone {
+            buttons |= BUTTON_LEFT;
+        } else if x > self.deadzone {
+            buttons |= BUTTON_RIGHT;
+        }
+        if y < -self.deadzone {
+            buttons |= BUTTON_UP;
+        } else if y > self.deadzone {
+            buttons |= BUTTON_DOWN;
+        }
+        buttons
+    }
+}
+
+fn find_device(name: &str) -> Option<Device> {
+    DEVICE_NAMES.iter().find(|(n, _)| *n == name).map(|(_, d)| *d)
+}
+
+fn find_pacing(name: &str) -> Option<Pacing> {
+    PACING_NAMES.iter().find(|(n, _)| *n == name).map(|(_, p)| *p)
 }
 
 fn get_conf_string(conf: &JsValue, key: &str) -> Option<String> {
@@ -51,6 +343,20 @@ fn get_conf_integer(conf: &JsValue, key: &str) -> Option<f64> {
     }
 }
 
+fn get_conf_bool(conf: &JsValue, key: &str) -> Option<bool> {
+    match Reflect::get(conf, &JsValue::from_str(key)) {
+        Ok(v) => v.as_bool(),
+        Err(_) => None
+    }
+}
+
+fn get_conf_function(conf: &JsValue, key: &str) -> Option<Function> {
+    match Reflect::get(conf, &JsValue::from_str(key)) {
+        Ok(v) => v.dyn_into::<Function>().ok(),
+        Err(_) => None
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -58,7 +364,82 @@ pub mod tests {
     pub fn mock() -> Configuration {
         Configuration {
             i18n: I18n::new(DEFAULT_LOCALE.to_string()),
-            fps: None
+            fps: None,
+            on_save: None,
+            keys: KeyMap::from_lookup(|_, _| None, |_| None, None),
+            gamepad: GamepadMap::default(),
+            device: None,
+            famicom: false,
+            microphone: false,
+            volume: 1.0,
+            mute: false,
+            pacing: Pacing::Interval,
+            bus_conflicts: None
         }
     }
+
+    #[test]
+    fn test_default_keys() {
+        let keys = KeyMap::from_lookup(|_, _| None, |_| None, None);
+        assert!(keys.is_mic("KeyM"));
+        assert_eq!(keys.find_family_key("BracketRight"), Some((0, 0x01)));
+        assert_eq!(keys.find_family_key("Space"), Some((8, 0x40)));
+        assert_eq!(keys.find_family_key("KeyE"), Some((6, 0x20)));
+        assert_eq!(keys.find_family_key("NumpadAdd"), None);
+        assert_eq!(keys.find_mat("Digit1"), Some(0));
+        assert_eq!(keys.find_mat("Equal"), Some(11));
+        assert!(DEFAULT_KEYS[0].iter().all(|k| keys.find_mat(k).is_none()));
+        assert_eq!(keys.find("KeyK"), Some((0, BUTTON_A)));
+        assert_eq!(keys.find("KeyD"), Some((0, BUTTON_RIGHT)));
+        assert_eq!(keys.find("Numpad5"), Some((1, BUTTON_START)));
+        assert_eq!(keys.find("ArrowUp"), Some((1, BUTTON_UP)));
+        assert_eq!(keys.find("Escape"), None);
+    }
+
+    #[test]
+    fn test_custom_keys() {
+        let keys = KeyMap::from_lookup(|player, name| match (player, name) {
+            (0, "a") => Some("Space".to_string()),
+            (1, "start") => Some("Enter".to_string()),
+            _ => None
+        }, |i| if i == 3 { Some("KeyR".to_string()) } else { None }, Some("KeyP".to_string()));
+        assert!(keys.is_mic("KeyP"));
+        assert!(!keys.is_mic("KeyM"));
+        assert_eq!(keys.find("Space"), Some((0, BUTTON_A)));
+        assert_eq!(keys.find("KeyK"), None);
+        assert_eq!(keys.find("Enter"), Some((1, BUTTON_START)));
+        assert_eq!(keys.find("KeyJ"), Some((0, BUTTON_B)));
+        assert_eq!(keys.find_mat("KeyR"), Some(3));
+        assert_eq!(keys.find_mat("Digit4"), None);
+        assert_eq!(keys.find_mat("Digit5"), Some(4));
+    }
+
+    #[test]
+    fn test_device() {
+        assert_eq!(find_device("fourScore"), Some(Device::FourScore));
+        assert_eq!(find_device("famicom4P"), Some(Device::Famicom4P));
+        assert_eq!(find_device("auto"), None);
+    }
+
+    #[test]
+    fn test_pacing() {
+        assert_eq!(find_pacing("animationFrame"), Some(Pacing::AnimationFrame));
+        assert_eq!(find_pacing("audio"), Some(Pacing::Audio));
+        assert_eq!(find_pacing("vsync"), None);
+        assert_eq!(mock().pacing(), Pacing::Interval);
+    }
+
+    #[test]
+    fn test_gamepad() {
+        let map = GamepadMap::default();
+        let buttons = map.buttons(|i| i == 1 || i == 9, |_| 0.0);
+        assert_eq!(buttons, BUTTON_A | BUTTON_START);
+        let buttons = map.buttons(|i| i == 12, |i| if i == 0 { 0.9 } else { 0.3 });
+        assert_eq!(buttons, BUTTON_UP | BUTTON_RIGHT);
+        let buttons = map.buttons(|_| false, |i| if i == 0 { -0.6 } else { 0.8 });
+        assert_eq!(buttons, BUTTON_LEFT | BUTTON_DOWN);
+        // The axis values within the deadzone
+        let buttons = map.buttons(|_| false, |_| -0.4);
+        assert_eq!(buttons, 0);
+    }
 }
\ No newline at end of file
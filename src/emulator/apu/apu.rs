@@ -0,0 +1,302 @@
+use super::pulse::Pulse;
+use super::triangle::Triangle;
+use super::noise::Noise;
+use super::dmc::DMC;
+use super::frame_counter::FrameCounter;
+use super::mixer::mix;
+use super::resampler::Resampler;
+use crate::rom::Timing;
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
+pub struct APU {
+    pulse_1: Pulse,
+    pulse_2: Pulse,
+    triangle: Triangle,
+    noise: Noise,
+    dmc: DMC,
+    frame_counter: FrameCounter,
+    // The pulse and the noise timers are clocked on the odd CPU cycles
+    odd_cycle: bool,
+    // The CPU clock rate in Hz
+    clock_rate: f64,
+    // Only available when the audio is played
+    resampler: Option<Resampler>
+}
+
+impl APU {
+    pub fn new(timing: &Timing) -> Self {
+        Self {
+            pulse_1: Pulse::new(true),
+            pulse_2: Pulse::new(false),
+            triangle: Triangle::new(),
+            noise: Noise::new(timing),
+            dmc: DMC::new(timing),
+            frame_counter: FrameCounter::new(timing),
+            odd_cycle: false,
+            clock_rate: clock_rate(timing),
+            resampler: None
+        }
+    }
+
+    // Resamples the output to the sample rate of the audio
+    pub fn set_sample_rate(&mut self, sample_rate: f64) {
+        self.resampler = Some(Resampler::new(self.clock_rate, sample_rate));
+    }
+
+    pub fn set_rate_control(&mut self, ratio: f64) {
+        if let Some(r) = self.resampler.as_mut() {
+            r.set_rate_control(ratio);
+        }
+    }
+
+    // Appends the samples resampled since the last call to out
+    pub fn take_samples(&mut self, out: &mut Vec<f32>) {
+        if let Some(r) = self.resampler.as_mut() {
+            r.read(out);
+        }
+    }
+
+    // Bit 0-3 report whether the length counters of pulse 1, pulse 2, triangle and noise are non-zero,
+    // bit 4 whether the DMC has bytes remaining, bit 6 the frame interrupt and bit 7 the DMC interrupt.
+    // Reading it acknowledges the frame interrupt.
+    pub fn read_status(&mut self) -> u8 {
+        let status = (self.pulse_1.active() as u8)
+            | (self.pulse_2.active() as u8) << 1
+            | (self.triangle.active() as u8) << 2
+            | (self.noise.active() as u8) << 3
+            | (self.dmc.active() as u8) << 4
+            | (self.frame_counter.irq() as u8) << 6
+            | (self.dmc.irq() as u8) << 7;
+        self.frame_counter.acknowledge();
+        status
+    }
+
+    pub fn irq(&self) -> bool {
+        self.dmc.irq() || self.frame_counter.irq()
+    }
+
+    // The address of the DMC sample byte to read, and the CPU is stalled for it
+    pub fn dmc_fetch_address(&self) -> Option<u16> {
+        self.dmc.fetch_address()
+    }
+
+    pub fn dmc_fill(&mut self, v: u8) {
+        self.dmc.fill(v);
+    }
+
+    pub fn write_register(&mut self, addr: u16, v: u8) {
+        match addr {
+            0x4000..=0x4003 => self.pulse_1.write(addr, v),
+            0x4004..=0x4007 => self.pulse_2.write(addr, v),
+            0x4008..=0x400B => self.triangle.write(addr, v),
+            0x400C..=0x400F => self.noise.write(addr, v),
+            0x4010..=0x4013 => self.dmc.write(addr, v),
+            0x4015 => {
+                self.pulse_1.set_enabled(v & 0x01 != 0);
+                self.pulse_2.set_enabled(v & 0x02 != 0);
+                self.triangle.set_enabled(v & 0x04 != 0);
+                self.noise.set_enabled(v & 0x08 != 0);
+                self.dmc.set_enabled(v & 0x10 != 0);
+            },
+            0x4017 => self.frame_counter.write(v, self.odd_cycle),
+            _ => ()
+        }
+    }
+
+    // Clocked every CPU cycle
+    pub fn tick(&mut self) {
+        let (quarter, half) = self.frame_counter.clock();
+        if quarter {
+            self.clock_quarter_frame();
+        }
+        if half {
+            self.clock_half_frame();
+        }
+        self.triangle.clock_timer();
+        self.dmc.clock_timer();
+        if self.odd_cycle {
+            self.pulse_1.clock_timer();
+            self.pulse_2.clock_timer();
+            self.noise.clock_timer();
+        }
+        self.odd_cycle = !self.odd_cycle;
+        let outputs = self.outputs();
+        if let Some(r) = self.resampler.as_mut() {
+            r.clock(mix(outputs));
+        }
+    }
+
+    // Clocks the envelopes and the linear counter
+    fn clock_quarter_frame(&mut self) {
+        self.pulse_1.clock_quarter_frame();
+        self.pulse_2.clock_quarter_frame();
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+
+    // Clocks the length counters and the sweep units
+    fn clock_half_frame(&mut self) {
+        self.pulse_1.clock_half_frame();
+        self.pulse_2.clock_half_frame();
+        self.triangle.clock_half_frame();
+        self.noise.clock_half_frame();
+    }
+
+    // The outputs of pulse 1, pulse 2, triangle, noise and DMC
+    fn outputs(&self) -> [u8; 5] {
+        [self.pulse_1.output(), self.pulse_2.output(), self.triangle.output(), self.noise.output(), self.dmc.output()]
+    }
+}
+
+// The CPU clock rates in Hz
+fn clock_rate(timing: &Timing) -> f64 {
+    match timing {
+        Timing::PAL => 1662607.0,
+        Timing::Dendy => 1773448.0,
+        Timing::NTSC | Timing::MultipleRegion => 1789773.0
+    }
+}
+
+impl Stateful for APU {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.pulse_1.save_state(w);
+        self.pulse_2.save_state(w);
+        self.triangle.save_state(w);
+        self.noise.save_state(w);
+        self.dmc.save_state(w);
+        self.frame_counter.save_state(w);
+        w.write_bool(self.odd_cycle);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.pulse_1.load_state(r)?;
+        self.pulse_2.load_state(r)?;
+        self.triangle.load_state(r)?;
+        self.noise.load_state(r)?;
+        self.dmc.load_state(r)?;
+        self.frame_counter.load_state(r)?;
+        self.odd_cycle = r.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status() {
+        let mut apu = APU::new(&Timing::NTSC);
+        apu.write_register(0x4003, 0x08);
+        assert_eq!(apu.read_status(), 0);
+
+        apu.write_register(0x4015, 0x03);
+        apu.write_register(0x4003, 0x08);
+        assert_eq!(apu.read_status(), 0x01);
+        apu.write_register(0x4007, 0x08);
+        assert_eq!(apu.read_status(), 0x03);
+
+        apu.write_register(0x4015, 0x02);
+        assert_eq!(apu.read_status(), 0x02);
+
+        apu.write_register(0x4015, 0x0C);
+        apu.write_register(0x400B, 0x08);
+        assert_eq!(apu.read_status(), 0x04);
+        apu.write_register(0x400F, 0x08);
+        assert_eq!(apu.read_status(), 0x0C);
+    }
+
+    #[test]
+    fn test_timers() {
+        let mut apu = APU::new(&Timing::NTSC);
+        apu.write_register(0x4015, 0x0F);
+        apu.write_register(0x4000, 0xBF);
+        apu.write_register(0x4002, 0x08);
+        apu.write_register(0x4003, 0x08);
+        apu.write_register(0x4008, 0x7F);
+        apu.write_register(0x400A, 0x08);
+        apu.write_register(0x400B, 0x08);
+        apu.clock_quarter_frame();
+        // The triangle steps twice as fast as the pulse with the same period
+        for _ in 0..18 {
+            apu.tick();
+        }
+        assert_eq!(apu.triangle.output(), 13);
+        assert_eq!(apu.pulse_1.output(), 15);
+    }
+
+    #[test]
+    fn test_length_count_down() {
+        let mut apu = APU::new(&Timing::NTSC);
+        apu.write_register(0x4015, 0x01);
+        // Length index 1 loads 254
+        apu.write_register(0x4003, 0x08);
+        for _ in 0..253 {
+            apu.clock_half_frame();
+        }
+        assert_eq!(apu.read_status(), 0x01);
+        apu.clock_half_frame();
+        assert_eq!(apu.read_status(), 0);
+    }
+
+    #[test]
+    fn test_frame_counter() {
+        let mut apu = APU::new(&Timing::NTSC);
+        apu.write_register(0x4015, 0x01);
+        // Length index 1 loads 254
+        apu.write_register(0x4003, 0x08);
+        apu.write_register(0x4017, 0x00);
+        for _ in 0..29833 {
+            apu.tick();
+        }
+        assert_eq!(apu.read_status(), 0x41);
+        // Acknowledged by reading
+        assert_eq!(apu.read_status(), 0x01);
+
+        // The 5-step mode has 2 half frames in 37282 cycles, besides the one right after the write
+        apu.write_register(0x4017, 0x80);
+        for _ in 0..(37282 * 127 + 3) {
+            apu.tick();
+        }
+        assert!(!apu.irq());
+        assert_eq!(apu.read_status(), 0);
+    }
+
+    #[test]
+    fn test_samples() {
+        let mut apu = APU::new(&Timing::PAL);
+        let mut samples = Vec::new();
+        apu.tick();
+        apu.take_samples(&mut samples);
+        assert!(samples.is_empty());
+
+        apu.set_sample_rate(44100.0);
+        for _ in 0..1662607 {
+            apu.tick();
+        }
+        apu.take_samples(&mut samples);
+        assert!((samples.len() as i32 - 44100).abs() <= 1);
+    }
+
+    #[test]
+    fn test_state() {
+        let mut apu = APU::new(&Timing::NTSC);
+        apu.write_register(0x4015, 0x03);
+        apu.write_register(0x4000, 0xBF);
+        apu.write_register(0x4006, 0x20);
+        apu.write_register(0x4007, 0x08);
+        let mut w = StateWriter::new();
+        apu.save_state(&mut w);
+        let data = w.finish();
+
+        let mut other = APU::new(&Timing::NTSC);
+        let mut r = StateReader::new(&data).ok().unwrap();
+        assert!(other.load_state(&mut r).is_ok());
+        assert!(r.finished());
+        assert_eq!(other.read_status(), 0x02);
+        let mut w = StateWriter::new();
+        other.save_state(&mut w);
+        assert_eq!(w.finish(), data);
+    }
+}
@@ -0,0 +1,286 @@
+use crate::rom::Timing;
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
+// The output rates in CPU cycles
+const RATES_NTSC: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+const RATES_PAL: [u16; 16] = [398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50];
+
+// The delta modulation channel, which plays 1-bit delta samples read from $C000-$FFFF
+pub struct DMC {
+    rates: &'static [u16; 16],
+    irq_enabled: bool,
+    loop_flag: bool,
+    irq: bool,
+    rate: u16,
+    timer: u16,
+    // The 7-bit output level
+    level: u8,
+
+    sample_address: u16,
+    sample_length: u16,
+    current_address: u16,
+    bytes_remaining: u16,
+    // Empty until the bus fetches the next byte
+    buffer: Option<u8>,
+
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool
+}
+
+impl DMC {
+    pub fn new(timing: &Timing) -> Self {
+        let rates = if timing == Timing::PAL {
+            &RATES_PAL
+        } else {
+            &RATES_NTSC
+        };
+        Self {
+            rates,
+            irq_enabled: false,
+            loop_flag: false,
+            irq: false,
+            rate: rates[0],
+            timer: 0,
+            level: 0,
+            sample_address: 0xC000,
+            sample_length: 1,
+            current_address: 0xC000,
+            bytes_remaining: 0,
+            buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true
+        }
+    }
+
+    // Writes the registers at $4010-$4013
+    pub fn write(&mut self, index: u16, v: u8) {
+        match index & 0x03 {
+            0 => {
+                self.irq_enabled = v & 0x80 != 0;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.loop_flag = v & 0x40 != 0;
+                self.rate = self.rates[(v & 0x0F) as usize];
+            },
+            1 => self.level = v & 0x7F,
+            2 => self.sample_address = 0xC000 | ((v as u16) << 6),
+            _ => self.sample_length = ((v as u16) << 4) + 1
+        }
+    }
+
+    // Writing $4015 acknowledges the interrupt, and restarts the sample if it's finished
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    pub fn active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn irq(&self) -> bool {
+        self.irq
+    }
+
+    // The address of the next sample byte if the buffer is empty
+    pub fn fetch_address(&self) -> Option<u16> {
+        if self.buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.current_address)
+        } else {
+            None
+        }
+    }
+
+    // Fills the buffer with the byte read from fetch_address
+    pub fn fill(&mut self, v: u8) {
+        self.buffer = Some(v);
+        // The address wraps around to $8000
+        self.current_address = self.current_address.checked_add(1).unwrap_or(0x8000);
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.loop_flag {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    // Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.rate - 1;
+            self.clock_output();
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    fn clock_output(&mut self) {
+        if !self.silence {
+            if self.shift & 0x01 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(v) => {
+                    self.shift = v;
+                    self.silence = false;
+                },
+                None => self.silence = true
+            }
+        }
+    }
+
+    // The level of 0-127
+    pub fn output(&self) -> u8 {
+        self.level
+    }
+}
+
+impl Stateful for DMC {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.irq_enabled);
+        w.write_bool(self.loop_flag);
+        w.write_bool(self.irq);
+        w.write_u16(self.rate);
+        w.write_u16(self.timer);
+        w.write_u8(self.level);
+        w.write_u16(self.sample_address);
+        w.write_u16(self.sample_length);
+        w.write_u16(self.current_address);
+        w.write_u16(self.bytes_remaining);
+        w.write_bool(self.buffer.is_some());
+        w.write_u8(self.buffer.unwrap_or(0));
+        w.write_u8(self.shift);
+        w.write_u8(self.bits_remaining);
+        w.write_bool(self.silence);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.irq_enabled = r.read_bool()?;
+        self.loop_flag = r.read_bool()?;
+        self.irq = r.read_bool()?;
+        self.rate = r.read_u16()?;
+        self.timer = r.read_u16()?;
+        self.level = r.read_u8()?;
+        self.sample_address = r.read_u16()?;
+        self.sample_length = r.read_u16()?;
+        self.current_address = r.read_u16()?;
+        self.bytes_remaining = r.read_u16()?;
+        let buffered = r.read_bool()?;
+        let buffer = r.read_u8()?;
+        self.buffer = if buffered { Some(buffer) } else { None };
+        self.shift = r.read_u8()?;
+        self.bits_remaining = r.read_u8()?;
+        self.silence = r.read_bool()?;
+        // The rate must be one of the table, otherwise the timer underflows
+        if self.rate == 0 || self.bits_remaining == 0 {
+            return Err(Message::MalformedState);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fetch() {
+        let mut dmc = DMC::new(&Timing::NTSC);
+        dmc.write(2, 0xFF);
+        dmc.write(3, 0x00);
+        assert_eq!(dmc.fetch_address(), None);
+        dmc.set_enabled(true);
+        assert!(dmc.active());
+        assert_eq!(dmc.fetch_address(), Some(0xFFC0));
+        dmc.fill(0x00);
+        // The buffer is full
+        assert_eq!(dmc.fetch_address(), None);
+        assert!(!dmc.active());
+    }
+
+    #[test]
+    fn test_wrap_and_loop() {
+        let mut dmc = DMC::new(&Timing::NTSC);
+        dmc.write(0, 0x40);
+        dmc.write(2, 0xFF);
+        dmc.write(3, 0x04);
+        dmc.set_enabled(true);
+        for _ in 0..64 {
+            dmc.fill(0);
+            dmc.buffer = None;
+        }
+        assert_eq!(dmc.current_address, 0x8000);
+        dmc.fill(0);
+        // Restarted from the sample address
+        assert_eq!(dmc.current_address, 0xFFC0);
+        assert_eq!(dmc.bytes_remaining, 65);
+        assert!(!dmc.irq());
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut dmc = DMC::new(&Timing::NTSC);
+        dmc.write(0, 0x80);
+        dmc.set_enabled(true);
+        dmc.fill(0);
+        assert!(dmc.irq());
+        // Acknowledged by writing $4015
+        dmc.set_enabled(false);
+        assert!(!dmc.irq());
+
+        dmc.set_enabled(true);
+        dmc.fill(0);
+        assert!(dmc.irq());
+        // Acknowledged by clearing the IRQ enabled flag
+        dmc.write(0, 0x00);
+        assert!(!dmc.irq());
+    }
+
+    #[test]
+    fn test_output() {
+        let mut dmc = DMC::new(&Timing::NTSC);
+        // The fastest rate, level 64
+        dmc.write(0, 0x0F);
+        dmc.write(1, 0x40);
+        dmc.set_enabled(true);
+        dmc.fill(0x0F);
+        // The output unit is silent until the 8 bits of the empty shift register are over
+        for _ in 0..(1 + 7 * 54) {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output(), 64);
+        // Bit 1 increases the level by 2, and bit 0 decreases it by 2
+        for _ in 0..(4 * 54) {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output(), 72);
+        for _ in 0..(4 * 54) {
+            dmc.clock_timer();
+        }
+        assert_eq!(dmc.output(), 64);
+    }
+}
@@ -0,0 +1,124 @@
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
+// The volume of the pulse and the noise channels, either constant or decaying
+pub struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    // The constant volume, or the period of the divider
+    volume: u8,
+    divider: u8,
+    decay: u8
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Self {
+            start: false,
+            looping: false,
+            constant: false,
+            volume: 0,
+            divider: 0,
+            decay: 0
+        }
+    }
+
+    // Bit 5 loops the decay, bit 4 selects the constant volume in bit 0-3
+    pub fn write(&mut self, v: u8) {
+        self.looping = v & 0x20 != 0;
+        self.constant = v & 0x10 != 0;
+        self.volume = v & 0x0F;
+    }
+
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    // Clocked by the quarter frames of the frame counter
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn output(&self) -> u8 {
+        if self.constant {
+            self.volume
+        } else {
+            self.decay
+        }
+    }
+}
+
+impl Stateful for Envelope {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.start);
+        w.write_bool(self.looping);
+        w.write_bool(self.constant);
+        w.write_u8(self.volume);
+        w.write_u8(self.divider);
+        w.write_u8(self.decay);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.start = r.read_bool()?;
+        self.looping = r.read_bool()?;
+        self.constant = r.read_bool()?;
+        self.volume = r.read_u8()?;
+        self.divider = r.read_u8()?;
+        self.decay = r.read_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constant() {
+        let mut envelope = Envelope::new();
+        envelope.write(0x17);
+        envelope.restart();
+        for _ in 0..20 {
+            envelope.clock();
+            assert_eq!(envelope.output(), 7);
+        }
+    }
+
+    #[test]
+    fn test_decay() {
+        let mut envelope = Envelope::new();
+        // The decay level decreases every 2 clocks
+        envelope.write(0x01);
+        envelope.restart();
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+        envelope.clock();
+        assert_eq!(envelope.output(), 14);
+        for _ in 0..40 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.output(), 0);
+
+        // Loops back to 15 when the divider runs out
+        envelope.write(0x20);
+        envelope.clock();
+        envelope.clock();
+        assert_eq!(envelope.output(), 15);
+    }
+}
@@ -0,0 +1,161 @@
+use crate::rom::Timing;
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
+// The CPU cycles of the 5 steps, the 4th step only sets the interrupt in the 4-step mode
+// and is skipped in the 5-step mode
+const STEPS_NTSC: [u16; 5] = [7457, 14913, 22371, 29829, 37281];
+const STEPS_PAL: [u16; 5] = [8313, 16627, 24939, 33253, 41565];
+
+// Clocks the envelopes and the linear counter on the quarter frames,
+// and the length counters and the sweep units on the half frames
+pub struct FrameCounter {
+    steps: &'static [u16; 5],
+    five_step: bool,
+    irq_inhibit: bool,
+    irq: bool,
+    cycle: u16,
+    // The sequencer is reset 3 or 4 CPU cycles after writing $4017
+    reset_delay: u8
+}
+
+impl FrameCounter {
+    pub fn new(timing: &Timing) -> Self {
+        let steps = if timing == Timing::PAL {
+            &STEPS_PAL
+        } else {
+            &STEPS_NTSC
+        };
+        Self {
+            steps,
+            five_step: false,
+            irq_inhibit: false,
+            irq: false,
+            cycle: 0,
+            reset_delay: 0
+        }
+    }
+
+    // Writes $4017, odd_cycle is the parity of the CPU cycle of the write
+    pub fn write(&mut self, v: u8, odd_cycle: bool) {
+        self.five_step = v & 0x80 != 0;
+        self.irq_inhibit = v & 0x40 != 0;
+        if self.irq_inhibit {
+            self.irq = false;
+        }
+        self.reset_delay = if odd_cycle { 4 } else { 3 };
+    }
+
+    pub fn irq(&self) -> bool {
+        self.irq
+    }
+
+    // Reading $4015 acknowledges the interrupt
+    pub fn acknowledge(&mut self) {
+        self.irq = false;
+    }
+
+    // Clocked every CPU cycle, returns whether it's a quarter frame and a half frame
+    pub fn clock(&mut self) -> (bool, bool) {
+        if self.reset_delay > 0 {
+            self.reset_delay -= 1;
+            if self.reset_delay == 0 {
+                self.cycle = 0;
+                // The 5-step mode clocks the units immediately
+                return (self.five_step, self.five_step);
+            }
+        }
+
+        self.cycle += 1;
+        let steps = self.steps;
+        let clocks = match self.cycle {
+            c if c == steps[0] || c == steps[2] => (true, false),
+            c if c == steps[1] => (true, true),
+            c if c == steps[3] && !self.five_step => {
+                if !self.irq_inhibit {
+                    self.irq = true;
+                }
+                (true, true)
+            },
+            c if c == steps[4] && self.five_step => (true, true),
+            _ => (false, false)
+        };
+        let period = if self.five_step { steps[4] } else { steps[3] } + 1;
+        if self.cycle >= period {
+            self.cycle = 0;
+        }
+        clocks
+    }
+}
+
+impl Stateful for FrameCounter {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.five_step);
+        w.write_bool(self.irq_inhibit);
+        w.write_bool(self.irq);
+        w.write_u16(self.cycle);
+        w.write_u8(self.reset_delay);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.five_step = r.read_bool()?;
+        self.irq_inhibit = r.read_bool()?;
+        self.irq = r.read_bool()?;
+        self.cycle = r.read_u16()?;
+        self.reset_delay = r.read_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Counts the quarter frames and the half frames in the given cycles
+    fn count(counter: &mut FrameCounter, cycles: u32) -> (u32, u32) {
+        let mut quarters = 0;
+        let mut halves = 0;
+        for _ in 0..cycles {
+            let (quarter, half) = counter.clock();
+            quarters += quarter as u32;
+            halves += half as u32;
+        }
+        (quarters, halves)
+    }
+
+    #[test]
+    fn test_four_step() {
+        let mut counter = FrameCounter::new(&Timing::NTSC);
+        assert_eq!(count(&mut counter, 7456), (0, 0));
+        assert_eq!(count(&mut counter, 1), (1, 0));
+        assert_eq!(count(&mut counter, 29830 - 7457), (3, 2));
+        assert!(counter.irq());
+        counter.acknowledge();
+        assert!(!counter.irq());
+        // The sequence repeats every 29830 cycles
+        assert_eq!(count(&mut counter, 29830), (4, 2));
+        assert!(counter.irq());
+    }
+
+    #[test]
+    fn test_five_step() {
+        let mut counter = FrameCounter::new(&Timing::NTSC);
+        counter.write(0x80, false);
+        assert_eq!(count(&mut counter, 2), (0, 0));
+        // Clocked immediately after the reset
+        assert_eq!(count(&mut counter, 1), (1, 1));
+        assert_eq!(count(&mut counter, 37282), (4, 2));
+        assert!(!counter.irq());
+    }
+
+    #[test]
+    fn test_irq_inhibit() {
+        let mut counter = FrameCounter::new(&Timing::PAL);
+        count(&mut counter, 33254);
+        assert!(counter.irq());
+        counter.write(0x40, true);
+        assert!(!counter.irq());
+        count(&mut counter, 33258);
+        assert!(!counter.irq());
+    }
+}
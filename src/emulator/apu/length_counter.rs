@@ -0,0 +1,104 @@
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
+// The lengths loaded by bit 3-7 of the last register of a channel
+const LENGTHS: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30
+];
+
+// Silences the channel when it counts down to 0
+pub struct LengthCounter {
+    enabled: bool,
+    halt: bool,
+    counter: u8
+}
+
+impl LengthCounter {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            halt: false,
+            counter: 0
+        }
+    }
+
+    // The counter is cleared and kept 0 while the channel is disabled by $4015
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    pub fn set_halt(&mut self, halt: bool) {
+        self.halt = halt;
+    }
+
+    pub fn load(&mut self, index: u8) {
+        if self.enabled {
+            self.counter = LENGTHS[(index & 0x1F) as usize];
+        }
+    }
+
+    // Clocked by the half frames of the frame counter
+    pub fn clock(&mut self) {
+        if !self.halt && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+impl Stateful for LengthCounter {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bool(self.enabled);
+        w.write_bool(self.halt);
+        w.write_u8(self.counter);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.enabled = r.read_bool()?;
+        self.halt = r.read_bool()?;
+        self.counter = r.read_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_count_down() {
+        let mut length = LengthCounter::new();
+        length.load(0x01);
+        assert!(!length.active());
+
+        length.set_enabled(true);
+        length.load(0x03);
+        assert_eq!(length.counter, 2);
+        length.clock();
+        assert!(length.active());
+        length.clock();
+        assert!(!length.active());
+        length.clock();
+        assert_eq!(length.counter, 0);
+    }
+
+    #[test]
+    fn test_halt_and_disable() {
+        let mut length = LengthCounter::new();
+        length.set_enabled(true);
+        length.load(0x00);
+        length.set_halt(true);
+        length.clock();
+        assert_eq!(length.counter, 10);
+
+        length.set_enabled(false);
+        assert!(!length.active());
+    }
+}
@@ -0,0 +1,34 @@
+// Mixes the outputs of pulse 1, pulse 2, triangle, noise and DMC into the level of 0-1,
+// with the non-linear formulas of the console's resistor network
+pub fn mix(outputs: [u8; 5]) -> f32 {
+    let [pulse_1, pulse_2, triangle, noise, dmc] = outputs;
+    let pulse = pulse_1 as f32 + pulse_2 as f32;
+    let pulse_out = if pulse == 0.0 {
+        0.0
+    } else {
+        95.88 / (8128.0 / pulse + 100.0)
+    };
+
+    let tnd = triangle as f32 / 8227.0 + noise as f32 / 12241.0 + dmc as f32 / 22638.0;
+    let tnd_out = if tnd == 0.0 {
+        0.0
+    } else {
+        159.79 / (1.0 / tnd + 100.0)
+    };
+    pulse_out + tnd_out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mix() {
+        assert_eq!(mix([0; 5]), 0.0);
+        assert!((mix([15, 15, 0, 0, 0]) - 0.2585).abs() < 1e-3);
+        assert!((mix([0, 0, 15, 15, 127]) - 0.7410).abs() < 1e-3);
+        assert!(mix([15, 15, 15, 15, 127]) <= 1.0);
+        // Non-linear, 2 pulses are quieter than twice of 1 pulse
+        assert!(mix([15, 15, 0, 0, 0]) < 2.0 * mix([15, 0, 0, 0, 0]));
+    }
+}
@@ -0,0 +1,12 @@
+mod apu;
+mod envelope;
+mod length_counter;
+mod pulse;
+mod triangle;
+mod noise;
+mod dmc;
+mod frame_counter;
+mod mixer;
+mod resampler;
+
+pub use apu::APU;
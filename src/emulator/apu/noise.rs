@@ -0,0 +1,173 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+use crate::rom::Timing;
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
+// The timer periods in CPU cycles
+const PERIODS_NTSC: [u16; 16] = [4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068];
+const PERIODS_PAL: [u16; 16] = [4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778];
+
+pub struct Noise {
+    periods: &'static [u16; 16],
+    envelope: Envelope,
+    length: LengthCounter,
+
+    // The short mode takes the feedback from bit 6 instead of bit 1
+    short_mode: bool,
+    period: u16,
+    timer: u16,
+    shift: u16
+}
+
+impl Noise {
+    pub fn new(timing: &Timing) -> Self {
+        let periods = if timing == Timing::PAL {
+            &PERIODS_PAL
+        } else {
+            &PERIODS_NTSC
+        };
+        Self {
+            periods,
+            envelope: Envelope::new(),
+            length: LengthCounter::new(),
+            short_mode: false,
+            period: 0,
+            timer: 0,
+            shift: 1
+        }
+    }
+
+    // Writes the registers at $400C-$400F, $400D is unused
+    pub fn write(&mut self, index: u16, v: u8) {
+        match index & 0x03 {
+            0 => {
+                self.length.set_halt(v & 0x20 != 0);
+                self.envelope.write(v);
+            },
+            1 => (),
+            2 => {
+                self.short_mode = v & 0x80 != 0;
+                // The timer is clocked every 2 CPU cycles
+                self.period = self.periods[(v & 0x0F) as usize] / 2 - 1;
+            },
+            _ => {
+                self.length.load(v >> 3);
+                self.envelope.restart();
+            }
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length.set_enabled(enabled);
+    }
+
+    pub fn active(&self) -> bool {
+        self.length.active()
+    }
+
+    // Clocked every 2 CPU cycles
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 0x01;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.shift & 0x01 != 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+impl Stateful for Noise {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.envelope.save_state(w);
+        self.length.save_state(w);
+        w.write_bool(self.short_mode);
+        w.write_u16(self.period);
+        w.write_u16(self.timer);
+        w.write_u16(self.shift);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.envelope.load_state(r)?;
+        self.length.load_state(r)?;
+        self.short_mode = r.read_bool()?;
+        self.period = r.read_u16()?;
+        self.timer = r.read_u16()?;
+        self.shift = r.read_u16()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The number of LFSR steps before it repeats
+    fn cycle_length(noise: &mut Noise) -> u32 {
+        let start = noise.shift;
+        let mut steps = 0;
+        loop {
+            for _ in 0..=noise.period {
+                noise.clock_timer();
+            }
+            steps += 1;
+            if noise.shift == start {
+                return steps;
+            }
+        }
+    }
+
+    #[test]
+    fn test_lfsr() {
+        let mut noise = Noise::new(&Timing::NTSC);
+        noise.write(2, 0x00);
+        assert_eq!(cycle_length(&mut noise), 32767);
+        noise.write(2, 0x80);
+        assert_eq!(cycle_length(&mut noise), 93);
+    }
+
+    #[test]
+    fn test_periods() {
+        let mut noise = Noise::new(&Timing::NTSC);
+        noise.write(2, 0x0F);
+        assert_eq!(noise.period, 2033);
+        let mut noise = Noise::new(&Timing::PAL);
+        noise.write(2, 0x0F);
+        assert_eq!(noise.period, 1888);
+        let mut noise = Noise::new(&Timing::Dendy);
+        noise.write(2, 0x02);
+        assert_eq!(noise.period, 7);
+    }
+
+    #[test]
+    fn test_output() {
+        let mut noise = Noise::new(&Timing::NTSC);
+        noise.set_enabled(true);
+        noise.write(0, 0x1C);
+        noise.write(3, 0x08);
+        assert!(noise.active());
+        // Bit 0 of the shift register mutes the channel
+        assert_eq!(noise.output(), 0);
+        noise.clock_timer();
+        assert_eq!(noise.shift, 0x4000);
+        assert_eq!(noise.output(), 12);
+    }
+}
@@ -0,0 +1,248 @@
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
+// The waveforms of 12.5%, 25%, 50% and 25% negated duty cycles
+const DUTIES: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1]
+];
+
+pub struct Pulse {
+    // Pulse 1 negates the sweep change in ones' complement, and pulse 2 in twos' complement
+    ones_complement: bool,
+    envelope: Envelope,
+    length: LengthCounter,
+
+    duty: u8,
+    step: u8,
+    period: u16,
+    timer: u16,
+
+    sweep_enabled: bool,
+    sweep_period: u8,
+    sweep_negate: bool,
+    sweep_shift: u8,
+    sweep_divider: u8,
+    sweep_reload: bool
+}
+
+impl Pulse {
+    pub fn new(ones_complement: bool) -> Self {
+        Self {
+            ones_complement,
+            envelope: Envelope::new(),
+            length: LengthCounter::new(),
+            duty: 0,
+            step: 0,
+            period: 0,
+            timer: 0,
+            sweep_enabled: false,
+            sweep_period: 0,
+            sweep_negate: false,
+            sweep_shift: 0,
+            sweep_divider: 0,
+            sweep_reload: false
+        }
+    }
+
+    // Writes the 4 registers at $4000-$4003 or $4004-$4007
+    pub fn write(&mut self, index: u16, v: u8) {
+        match index & 0x03 {
+            0 => {
+                self.duty = v >> 6;
+                self.length.set_halt(v & 0x20 != 0);
+                self.envelope.write(v);
+            },
+            1 => {
+                self.sweep_enabled = v & 0x80 != 0;
+                self.sweep_period = (v >> 4) & 0x07;
+                self.sweep_negate = v & 0x08 != 0;
+                self.sweep_shift = v & 0x07;
+                self.sweep_reload = true;
+            },
+            2 => self.period = (self.period & 0x0700) | v as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((v as u16 & 0x07) << 8);
+                self.length.load(v >> 3);
+                self.envelope.restart();
+                self.step = 0;
+            }
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length.set_enabled(enabled);
+    }
+
+    pub fn active(&self) -> bool {
+        self.length.active()
+    }
+
+    // Clocked every 2 CPU cycles
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) & 0x07;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+        if self.sweep_divider == 0 && self.sweep_enabled && self.sweep_shift > 0 && !self.muted() {
+            self.period = self.target_period();
+        }
+        if self.sweep_divider == 0 || self.sweep_reload {
+            self.sweep_divider = self.sweep_period;
+            self.sweep_reload = false;
+        } else {
+            self.sweep_divider -= 1;
+        }
+    }
+
+    fn target_period(&self) -> u16 {
+        let change = self.period >> self.sweep_shift;
+        if self.sweep_negate {
+            let change = if self.ones_complement { change + 1 } else { change };
+            self.period.saturating_sub(change)
+        } else {
+            self.period + change
+        }
+    }
+
+    // The sweep unit mutes the channel even if it's disabled
+    fn muted(&self) -> bool {
+        self.period < 8 || self.target_period() > 0x07FF
+    }
+
+    // The volume of 0-15
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.muted() || DUTIES[self.duty as usize][self.step as usize] == 0 {
+            0
+        } else {
+            self.envelope.output()
+        }
+    }
+}
+
+impl Stateful for Pulse {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.envelope.save_state(w);
+        self.length.save_state(w);
+        w.write_u8(self.duty);
+        w.write_u8(self.step);
+        w.write_u16(self.period);
+        w.write_u16(self.timer);
+        w.write_bool(self.sweep_enabled);
+        w.write_u8(self.sweep_period);
+        w.write_bool(self.sweep_negate);
+        w.write_u8(self.sweep_shift);
+        w.write_u8(self.sweep_divider);
+        w.write_bool(self.sweep_reload);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.envelope.load_state(r)?;
+        self.length.load_state(r)?;
+        self.duty = r.read_u8()?;
+        self.step = r.read_u8()?;
+        self.period = r.read_u16()?;
+        self.timer = r.read_u16()?;
+        self.sweep_enabled = r.read_bool()?;
+        self.sweep_period = r.read_u8()?;
+        self.sweep_negate = r.read_bool()?;
+        self.sweep_shift = r.read_u8()?;
+        self.sweep_divider = r.read_u8()?;
+        self.sweep_reload = r.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_pulse(ones_complement: bool) -> Pulse {
+        let mut pulse = Pulse::new(ones_complement);
+        pulse.set_enabled(true);
+        // 50% duty, constant volume 10
+        pulse.write(0, 0xBA);
+        pulse.write(2, 0x00);
+        pulse.write(3, 0x09);
+        pulse
+    }
+
+    #[test]
+    fn test_duty() {
+        let mut pulse = make_pulse(false);
+        assert_eq!(pulse.period, 0x100);
+        let mut wave = Vec::new();
+        for _ in 0..8 {
+            // A step lasts period + 1 clocks
+            for _ in 0..=pulse.period {
+                pulse.clock_timer();
+            }
+            wave.push(pulse.output());
+        }
+        assert_eq!(wave, [10, 10, 10, 10, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_length() {
+        let mut pulse = make_pulse(false);
+        assert!(pulse.active());
+        pulse.set_enabled(false);
+        assert!(!pulse.active());
+        assert_eq!(pulse.output(), 0);
+        pulse.write(3, 0x09);
+        assert!(!pulse.active());
+    }
+
+    #[test]
+    fn test_sweep() {
+        let mut pulse = make_pulse(false);
+        // Enabled, period 0, shift 1
+        pulse.write(1, 0x81);
+        pulse.clock_half_frame();
+        assert_eq!(pulse.period, 0x180);
+        pulse.clock_half_frame();
+        assert_eq!(pulse.period, 0x240);
+    }
+
+    #[test]
+    fn test_sweep_negate() {
+        let mut pulse_1 = make_pulse(true);
+        let mut pulse_2 = make_pulse(false);
+        pulse_1.write(1, 0x89);
+        pulse_2.write(1, 0x89);
+        pulse_1.clock_half_frame();
+        pulse_2.clock_half_frame();
+        assert_eq!(pulse_1.period, 0x7F);
+        assert_eq!(pulse_2.period, 0x80);
+    }
+
+    #[test]
+    fn test_sweep_mute() {
+        let mut pulse = make_pulse(false);
+        pulse.write(2, 0x07);
+        pulse.write(3, 0x08);
+        assert!(pulse.muted());
+        // The target period overflows even if the sweep is disabled
+        pulse.write(2, 0xFF);
+        pulse.write(3, 0x0F);
+        pulse.write(1, 0x01);
+        assert!(pulse.muted());
+        assert_eq!(pulse.output(), 0);
+        pulse.write(1, 0x08);
+        assert!(!pulse.muted());
+    }
+}
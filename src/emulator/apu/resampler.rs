@@ -0,0 +1,173 @@
+use std::f64::consts::PI;
+
+// The band-limited step is split into phases by the fraction of the sample it starts at
+const PHASES: usize = 32;
+const TAPS: usize = 16;
+// The cutoff frequency relative to the sample rate, below the Nyquist frequency
+const CUTOFF: f64 = 0.45;
+// The high-pass filter removes the DC offset of the mixer as the console does
+const HIGH_PASS_HZ: f64 = 90.0;
+
+// Resamples the level of every CPU cycle to the audio sample rate.
+// Every change of the level is added as a band-limited step, so the frequencies
+// above the Nyquist frequency of the sample rate are not aliased.
+pub struct Resampler {
+    // The samples per CPU cycle, and the one adjusted by the rate control
+    base_step: f64,
+    step: f64,
+    // The time of the current CPU cycle in samples, from the first unfinished sample
+    time: f64,
+    level: f32,
+    kernel: Vec<[f32; TAPS]>,
+    // The differences between the unfinished samples
+    deltas: Vec<f32>,
+    sum: f32,
+
+    alpha: f32,
+    last_in: f32,
+    last_out: f32
+}
+
+impl Resampler {
+    pub fn new(clock_rate: f64, sample_rate: f64) -> Self {
+        let rc = 1.0 / (2.0 * PI * HIGH_PASS_HZ);
+        let dt = 1.0 / sample_rate;
+        Self {
+            base_step: sample_rate / clock_rate,
+            step: sample_rate / clock_rate,
+            time: 0.0,
+            level: 0.0,
+            kernel: (0..PHASES).map(|p| make_phase(p as f64 / PHASES as f64)).collect(),
+            deltas: vec![0.0; TAPS],
+            sum: 0.0,
+            alpha: (rc / (rc + dt)) as f32,
+            last_in: 0.0,
+            last_out: 0.0
+        }
+    }
+
+    // Makes more or less samples by the ratio, to keep the audio buffer from running out or overflowing
+    pub fn set_rate_control(&mut self, ratio: f64) {
+        self.step = self.base_step * ratio;
+    }
+
+    // Clocked every CPU cycle with the mixed level
+    pub fn clock(&mut self, level: f32) {
+        if level != self.level {
+            self.add_delta(level - self.level);
+            self.level = level;
+        }
+        self.time += self.step;
+    }
+
+    fn add_delta(&mut self, delta: f32) {
+        let index = self.time as usize;
+        let phase = ((self.time - index as f64) * PHASES as f64) as usize;
+        if self.deltas.len() < index + TAPS {
+            self.deltas.resize(index + TAPS, 0.0);
+        }
+        for (d, k) in self.deltas[index..].iter_mut().zip(self.kernel[phase.min(PHASES - 1)].iter()) {
+            *d += delta * k;
+        }
+    }
+
+    // Appends the finished samples to out
+    pub fn read(&mut self, out: &mut Vec<f32>) {
+        let count = self.time as usize;
+        if self.deltas.len() < count + TAPS {
+            self.deltas.resize(count + TAPS, 0.0);
+        }
+        for i in 0..count {
+            self.sum += self.deltas[i];
+            let v = self.alpha * (self.last_out + self.sum - self.last_in);
+            self.last_in = self.sum;
+            self.last_out = v;
+            out.push(v);
+        }
+        self.deltas.drain(..count);
+        self.time -= count as f64;
+    }
+}
+
+// The windowed sinc impulse starting at the fraction of the sample, normalized to the sum of 1
+fn make_phase(fraction: f64) -> [f32; TAPS] {
+    let mut taps = [0.0; TAPS];
+    for (i, t) in taps.iter_mut().enumerate() {
+        let d = i as f64 - fraction - (TAPS / 2 - 1) as f64;
+        let x = 2.0 * CUTOFF * d;
+        let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+        let w = (d / TAPS as f64 + 0.5).clamp(0.0, 1.0);
+        let window = 0.42 - 0.5 * (2.0 * PI * w).cos() + 0.08 * (4.0 * PI * w).cos();
+        *t = sinc * window;
+    }
+    let sum: f64 = taps.iter().sum();
+    let mut phase = [0.0; TAPS];
+    for (p, t) in phase.iter_mut().zip(taps.iter()) {
+        *p = (t / sum) as f32;
+    }
+    phase
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLOCK_RATE: f64 = 1789773.0;
+    const SAMPLE_RATE: f64 = 48000.0;
+
+    // The root mean square of the samples of a square wave in the frequency, after the filter settles
+    fn square_level(frequency: f64) -> f32 {
+        let mut resampler = Resampler::new(CLOCK_RATE, SAMPLE_RATE);
+        let half_period = CLOCK_RATE / frequency / 2.0;
+        let mut samples = Vec::new();
+        for cycle in 0..(CLOCK_RATE as usize / 10) {
+            let high = (cycle as f64 / half_period) as usize & 0x01 != 0;
+            resampler.clock(if high { 0.5 } else { 0.0 });
+        }
+        resampler.read(&mut samples);
+        let tail = &samples[samples.len() / 2..];
+        (tail.iter().map(|s| s * s).sum::<f32>() / tail.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn test_sample_count() {
+        let mut resampler = Resampler::new(CLOCK_RATE, SAMPLE_RATE);
+        let mut samples = Vec::new();
+        for _ in 0..CLOCK_RATE as usize {
+            resampler.clock(0.0);
+        }
+        resampler.read(&mut samples);
+        assert!((samples.len() as i32 - SAMPLE_RATE as i32).abs() <= 1);
+    }
+
+    #[test]
+    fn test_rate_control() {
+        let mut resampler = Resampler::new(CLOCK_RATE, SAMPLE_RATE);
+        let mut samples = Vec::new();
+        resampler.set_rate_control(1.005);
+        for _ in 0..CLOCK_RATE as usize {
+            resampler.clock(0.0);
+        }
+        resampler.read(&mut samples);
+        assert!((samples.len() as i32 - 48240).abs() <= 1);
+    }
+
+    #[test]
+    fn test_high_pass() {
+        let mut resampler = Resampler::new(CLOCK_RATE, SAMPLE_RATE);
+        let mut samples = Vec::new();
+        for _ in 0..(CLOCK_RATE as usize / 10) {
+            resampler.clock(0.8);
+        }
+        resampler.read(&mut samples);
+        assert!(samples.iter().any(|s| *s > 0.5));
+        assert!(samples.last().unwrap().abs() < 0.01);
+    }
+
+    #[test]
+    fn test_band_limited() {
+        // The square wave of 1kHz passes, the one of 40kHz would be aliased without the filter
+        assert!(square_level(1000.0) > 0.2);
+        assert!(square_level(40000.0) < 0.02);
+    }
+}
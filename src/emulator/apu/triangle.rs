@@ -0,0 +1,186 @@
+use super::length_counter::LengthCounter;
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15
+];
+
+pub struct Triangle {
+    length: LengthCounter,
+
+    // Halts the length counter and keeps reloading the linear counter
+    control: bool,
+    linear_reload_value: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+
+    step: u8,
+    period: u16,
+    timer: u16
+}
+
+impl Triangle {
+    pub fn new() -> Self {
+        Self {
+            length: LengthCounter::new(),
+            control: false,
+            linear_reload_value: 0,
+            linear_counter: 0,
+            linear_reload: false,
+            step: 0,
+            period: 0,
+            timer: 0
+        }
+    }
+
+    // Writes the registers at $4008-$400B, $4009 is unused
+    pub fn write(&mut self, index: u16, v: u8) {
+        match index & 0x03 {
+            0 => {
+                self.control = v & 0x80 != 0;
+                self.length.set_halt(self.control);
+                self.linear_reload_value = v & 0x7F;
+            },
+            1 => (),
+            2 => self.period = (self.period & 0x0700) | v as u16,
+            _ => {
+                self.period = (self.period & 0x00FF) | ((v as u16 & 0x07) << 8);
+                self.length.load(v >> 3);
+                self.linear_reload = true;
+            }
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length.set_enabled(enabled);
+    }
+
+    pub fn active(&self) -> bool {
+        self.length.active()
+    }
+
+    // Clocked every CPU cycle
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            // The ultrasonic frequencies are inaudible but pop on the speaker,
+            // so the sequencer holds instead.
+            if self.linear_counter > 0 && self.length.active() && self.period >= 2 {
+                self.step = (self.step + 1) & 0x1F;
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_reload_value;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    // The channel is silenced by holding the sequencer, so the output stays where it is
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.step as usize]
+    }
+}
+
+impl Stateful for Triangle {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.length.save_state(w);
+        w.write_bool(self.control);
+        w.write_u8(self.linear_reload_value);
+        w.write_u8(self.linear_counter);
+        w.write_bool(self.linear_reload);
+        w.write_u8(self.step);
+        w.write_u16(self.period);
+        w.write_u16(self.timer);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.length.load_state(r)?;
+        self.control = r.read_bool()?;
+        self.linear_reload_value = r.read_u8()?;
+        self.linear_counter = r.read_u8()?;
+        self.linear_reload = r.read_bool()?;
+        self.step = r.read_u8()?;
+        self.period = r.read_u16()?;
+        self.timer = r.read_u16()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_triangle(period: u16) -> Triangle {
+        let mut triangle = Triangle::new();
+        triangle.set_enabled(true);
+        triangle.write(0, 0x10);
+        triangle.write(2, period as u8);
+        triangle.write(3, 0x08 | (period >> 8) as u8);
+        triangle.clock_quarter_frame();
+        triangle
+    }
+
+    #[test]
+    fn test_sequence() {
+        let mut triangle = make_triangle(0x20);
+        let mut wave = Vec::new();
+        for _ in 0..32 {
+            for _ in 0..=0x20 {
+                triangle.clock_timer();
+            }
+            wave.push(triangle.output());
+        }
+        assert_eq!(wave[0], 14);
+        assert_eq!(wave[14], 0);
+        assert_eq!(wave[15], 0);
+        assert_eq!(wave[30], 15);
+        assert_eq!(wave[31], 15);
+    }
+
+    #[test]
+    fn test_linear_counter() {
+        let mut triangle = make_triangle(0x20);
+        assert_eq!(triangle.linear_counter, 0x10);
+        for _ in 0..0x10 {
+            triangle.clock_quarter_frame();
+        }
+        assert_eq!(triangle.linear_counter, 0);
+        let output = triangle.output();
+        for _ in 0..100 {
+            triangle.clock_timer();
+        }
+        assert_eq!(triangle.output(), output);
+
+        // The control flag keeps reloading the linear counter
+        triangle.write(0, 0x85);
+        triangle.write(3, 0x08);
+        triangle.clock_quarter_frame();
+        triangle.clock_quarter_frame();
+        assert_eq!(triangle.linear_counter, 5);
+    }
+
+    #[test]
+    fn test_ultrasonic() {
+        let mut triangle = make_triangle(1);
+        let output = triangle.output();
+        for _ in 0..100 {
+            triangle.clock_timer();
+        }
+        assert_eq!(triangle.output(), output);
+    }
+}
@@ -0,0 +1,218 @@
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use wasm_bindgen::prelude::{Closure, JsValue};
+use wasm_bindgen::JsCast;
+use web_sys::{
+    AudioContext, AudioContextState, AudioWorkletNode, GainNode, MessageEvent,
+    Blob, BlobPropertyBag, Url
+};
+use js_sys::{Array, Float32Array, Object, Reflect};
+
+// The AudioWorkletProcessor playing the samples from its ring buffer
+const WORKLET: &str = include_str!("worklet.js");
+const PROCESSOR_NAME: &str = "pixel-hero-audio";
+// The samples kept in the ring buffer of the worklet, which is a half of its capacity
+pub const TARGET_BUFFERED: usize = 2048;
+// The resampling rate is adjusted within this ratio to keep the buffer around the target
+const MAX_RATE_DELTA: f64 = 0.005;
+// The frames are skipped above this, when they're made faster than the rate control can absorb,
+// so the ring buffer of the worklet has the room of a frame left
+const MAX_BUFFERED: usize = TARGET_BUFFERED * 3 / 2;
+
+// Streams the APU samples to an AudioWorklet through Web Audio
+pub struct Audio {
+    ctx: AudioContext,
+    // Available after the worklet module is loaded
+    node: Rc<RefCell<Option<AudioWorkletNode>>>,
+    // The samples in the ring buffer, reported by the worklet and increased by the posted ones
+    buffered: Rc<Cell<usize>>,
+    // The samples of the current frame
+    samples: Vec<f32>
+}
+
+impl Audio {
+    pub fn new(volume: f64) -> Result<Self, JsValue> {
+        let ctx = AudioContext::new()?;
+        // Suspended if it's not created by a user event
+        let _ = ctx.resume();
+        let gain = ctx.create_gain()?;
+        gain.gain().set_value(volume as f32);
+        gain.connect_with_audio_node(&ctx.destination())?;
+        let node = Rc::new(RefCell::new(None));
+        let buffered = Rc::new(Cell::new(0));
+        load_worklet(&ctx, gain, node.clone(), buffered.clone())?;
+        Ok(Self {
+            ctx,
+            node,
+            buffered,
+            samples: Vec::new()
+        })
+    }
+
+    pub fn sample_rate(&self) -> f64 {
+        self.ctx.sample_rate() as f64
+    }
+
+    pub fn samples_mut(&mut self) -> &mut Vec<f32> {
+        &mut self.samples
+    }
+
+    // Whether the worklet is loaded and playing
+    pub fn ready(&self) -> bool {
+        self.node.borrow().is_some() && self.ctx.state() == AudioContextState::Running
+    }
+
+    pub fn buffered(&self) -> usize {
+        self.buffered.get()
+    }
+
+    // The ratio to the resampling rate, more samples are made when the buffer is lower than the target
+    pub fn rate_control(&self) -> f64 {
+        rate_control(self.buffered.get())
+    }
+
+    // Whether the buffer is too full to take another frame
+    pub fn overflowing(&self) -> bool {
+        overflowing(self.ready(), self.buffered.get())
+    }
+
+    // Posts the samples of the frame to the worklet.
+    // They're dropped if it's not loaded yet or suspended, since it plays nothing to report the buffer.
+    pub fn flush(&mut self) {
+        let ready = self.ready();
+        if ready {
+            if let Some(Ok(port)) = self.node.borrow().as_ref().map(|n| n.port()) {
+                let _ = port.post_message(&Float32Array::from(self.samples.as_slice()));
+            }
+        }
+        self.buffered.set(flushed(ready, self.buffered.get(), self.samples.len()));
+        self.samples.clear();
+    }
+}
+
+impl Drop for Audio {
+    fn drop(&mut self) {
+        let _ = self.ctx.close();
+    }
+}
+
+fn rate_control(buffered: usize) -> f64 {
+    let deviation = (TARGET_BUFFERED as f64 - buffered as f64) / TARGET_BUFFERED as f64;
+    1.0 + MAX_RATE_DELTA * deviation.clamp(-1.0, 1.0)
+}
+
+// A suspended context never overflows, so the frames keep running without audio
+fn overflowing(ready: bool, buffered: usize) -> bool {
+    ready && buffered > MAX_BUFFERED
+}
+
+// The samples in the buffer after a frame is flushed, they're only counted when they're posted
+fn flushed(ready: bool, buffered: usize, count: usize) -> usize {
+    if ready {
+        buffered + count
+    } else {
+        buffered
+    }
+}
+
+fn load_worklet(ctx: &AudioContext, gain: GainNode, node: Rc<RefCell<Option<AudioWorkletNode>>>,
+                buffered: Rc<Cell<usize>>) -> Result<(), JsValue> {
+    let options = Object::new();
+    Reflect::set(&options, &JsValue::from_str("type"), &JsValue::from_str("text/javascript"))?;
+    let blob = Blob::new_with_str_sequence_and_options(
+        &Array::of1(&JsValue::from_str(WORKLET)),
+        options.unchecked_ref::<BlobPropertyBag>()
+    )?;
+    let url = Url::create_object_url_with_blob(&blob)?;
+    let promise = ctx.audio_worklet()?.add_module(&url)?;
+    let ctx = ctx.clone();
+    let callback = Closure::wrap(Box::new(move |_: JsValue| {
+        let _ = Url::revoke_object_url(&url);
+        let _ = connect_worklet(&ctx, &gain, &node, &buffered);
+    }) as Box<dyn FnMut(JsValue)>);
+    let _ = promise.then(&callback);
+    callback.forget();
+    Ok(())
+}
+
+fn connect_worklet(ctx: &AudioContext, gain: &GainNode, node: &Rc<RefCell<Option<AudioWorkletNode>>>,
+                   buffered: &Rc<Cell<usize>>) -> Result<(), JsValue> {
+    let worklet = AudioWorkletNode::new(ctx, PROCESSOR_NAME)?;
+    worklet.connect_with_audio_node(gain)?;
+    let buffered = buffered.clone();
+    let on_message = Closure::wrap(Box::new(move |e: MessageEvent| {
+        if let Some(size) = e.data().as_f64() {
+            buffered.set(size as usize);
+        }
+    }) as Box<dyn FnMut(MessageEvent)>);
+    worklet.port()?.set_onmessage(Some(on_message.as_ref().unchecked_ref()));
+    on_message.forget();
+    *node.borrow_mut() = Some(worklet);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_control() {
+        assert_eq!(rate_control(TARGET_BUFFERED), 1.0);
+        assert_eq!(rate_control(0), 1.0 + MAX_RATE_DELTA);
+        assert_eq!(rate_control(TARGET_BUFFERED * 3), 1.0 - MAX_RATE_DELTA);
+        assert!(rate_control(TARGET_BUFFERED / 2) > 1.0);
+    }
+
+    #[test]
+    fn test_fast_producer() {
+        // The capacity of the ring buffer in worklet.js, played in the blocks of 128 samples
+        const CAPACITY: usize = 4096;
+        const SAMPLE_RATE: usize = 48000;
+        // A timer of 16ms runs 62.5 frames per second, while the audio plays 60.0988
+        let frame_period = SAMPLE_RATE * 16 / 1000;
+        let frame_samples = SAMPLE_RATE as f64 / 60.0988;
+        let (mut ring, mut buffered, mut next_frame) = (0, 0, 0);
+        let mut skipped = 0;
+        for (block, time) in (0..SAMPLE_RATE * 10).step_by(128).enumerate() {
+            ring -= ring.min(128);
+            if block % 4 == 3 {
+                buffered = ring;
+            }
+            while next_frame <= time {
+                if overflowing(true, buffered) {
+                    skipped += 1;
+                } else {
+                    let count = (frame_samples * rate_control(buffered)) as usize;
+                    assert!(ring + count <= CAPACITY);
+                    ring += count;
+                    buffered = flushed(true, buffered, count);
+                }
+                next_frame += frame_period;
+            }
+        }
+        assert!(skipped > 0);
+        assert!(ring > TARGET_BUFFERED / 2);
+    }
+
+    #[test]
+    fn test_suspended() {
+        // The context suspended by the autoplay policy plays and reports nothing,
+        // so the frames of 10 seconds are all run with their samples dropped
+        let mut buffered = 0;
+        let mut skipped = 0;
+        for _ in 0..600 {
+            if overflowing(false, buffered) {
+                skipped += 1;
+            } else {
+                buffered = flushed(false, buffered, 800);
+            }
+        }
+        assert_eq!(skipped, 0);
+        assert_eq!(buffered, 0);
+        // The frames are counted after the context is resumed
+        buffered = flushed(true, buffered, 800);
+        assert_eq!(buffered, 800);
+        assert!(!overflowing(false, TARGET_BUFFERED * 2));
+        assert!(overflowing(true, TARGET_BUFFERED * 2));
+    }
+}
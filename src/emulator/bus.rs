@@ -1,11 +1,16 @@
 use web_sys::CanvasRenderingContext2d;
 use crate::conf::Configuration;
-use crate::emulator::controller::Controller;
+use crate::emulator::controller::{Controller, Device};
 use super::cpu::CPU;
 use super::ppu::PPU;
 use super::apu::APU;
 use super::memory::Memory;
 use crate::rom::Rom;
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
+// The CPU cycles stolen by a DMC sample fetch
+const DMC_STALL_CLK: u16 = 4;
 
 pub struct Bus {
     cpu: CPU,
@@ -18,23 +23,28 @@ pub struct Bus {
     nmi_flag: bool,
     brk_flag: bool,
     irq_flag: bool,
-    dma_clk: u16
+    dma_clk: u16,
+    // The last value on the data bus, which unmapped reads return
+    open_bus: u8
 }
 
 impl Bus {
-    pub fn new(rom: Rom, ctx: Option<CanvasRenderingContext2d>, _conf: &Configuration) -> Bus {
+    pub fn new(rom: Rom, ctx: Option<CanvasRenderingContext2d>, conf: &Configuration) -> Bus {
+        let device = conf.device().unwrap_or_else(|| Device::from_exp_device(rom.exp_device()));
         let mut bus = Bus {
             cpu: CPU::new(),
             ppu: PPU::new(ctx, &rom),
-            apu: APU::new(),
+            apu: APU::new(rom.timing()),
             memory: Memory::new(),
-            controller: Controller::new(),
+            controller: Controller::new(device),
             rom,
             nmi_flag: false,
             brk_flag: false,
             irq_flag: false,
-            dma_clk: 0
+            dma_clk: 0,
+            open_bus: 0
         };
+        bus.controller.set_famicom(conf.famicom());
         let pc = bus.read(0xFFFC) as u16 | (bus.read(0xFFFD) as u16) << 8;
         bus.cpu.goto(pc);
         bus
@@ -42,39 +52,54 @@ impl Bus {
 
     pub fn read(&mut self, addr: u16) -> u8 {
         let mark = addr & 0xF000;
-        if mark == 0 || mark == 0x1000 {
+        let v = if mark == 0 || mark == 0x1000 {
             self.memory.read(addr)
         } else if mark == 0x2000 || mark == 0x3000 {
-            self.ppu.read_register(addr, &self.rom)
+            self.ppu.read_register(addr, &mut self.rom)
+        } else if addr >= 0x8000 {
+            self.rom.mapper_mut().read_prg(addr)
+        } else if addr >= 0x6000 {
+            self.rom.mapper_mut().read_ram(addr).unwrap_or(self.open_bus)
         } else if addr >= 0x4020 {
-            self.rom.mapper().read_prg(addr)
+            self.rom.mapper_mut().read_expansion(addr).unwrap_or(self.open_bus)
         } else if addr == 0x4015 {
             self.apu.read_status()
         } else if addr == 0x4016 || addr == 0x4017 {
-            self.controller.read_joy((addr - 0x4016) as u8)
+            if let Some((x, y)) = self.controller.light_target() {
+                let light = self.ppu.sense_light(x, y);
+                self.controller.set_light(light);
+            }
+            (self.open_bus & 0xE0) | self.controller.read_joy((addr - 0x4016) as u8)
         } else {
-            panic!("${:X} is not readable.", addr);
-        }
+            // Write-only APU registers and the disabled test registers at $4018-$401F
+            self.open_bus
+        };
+        self.open_bus = v;
+        v
     }
 
     pub fn write(&mut self, addr: u16, v: u8) {
+        self.open_bus = v;
         let mark = addr & 0xF000;
         if mark == 0 || mark == 0x1000 {
             self.memory.write(addr, v);
         } else if mark == 0x2000 || mark == 0x3000 {
+            self.rom.mapper_mut().notify_ppu_register(addr, v);
             if self.ppu.write_register(addr, v, &mut self.rom) {
                 self.nmi_flag = true;
             }
-        } else if addr >= 0x4020 {
+        } else if addr >= 0x8000 {
             self.rom.mapper_mut().write_prg(addr, v);
+        } else if addr >= 0x6000 {
+            self.rom.mapper_mut().write_ram(addr, v);
+        } else if addr >= 0x4020 {
+            self.rom.mapper_mut().write_expansion(addr, v);
         } else if addr == 0x4014 {
             self.start_dma(v);
         } else if addr == 0x4016 {
             self.controller.write_joy_strode(v);
         } else if addr <= 0x4017 {
             self.apu.write_register(addr, v);
-        } else {
-            panic!("${:X} is not writable.", addr);
         }
     }
 
@@ -168,13 +193,26 @@ impl Bus {
     }
 
     pub fn ppu_ticks(&mut self, t: u8) -> bool {
-        let result = self.ppu.ticks(t, &self.rom);
+        let result = self.ppu.ticks(t, &mut self.rom);
         if result.1 {
             self.nmi_flag = true;
         }
         result.0
     }
 
+    // Clocks the APU, and stalls the CPU while the DMC reads the samples
+    pub fn apu_ticks(&mut self, t: u8) {
+        for _ in 0..t {
+            self.apu.tick();
+            if let Some(addr) = self.apu.dmc_fetch_address() {
+                let v = self.read(addr);
+                self.apu.dmc_fill(v);
+                self.dma_clk += DMC_STALL_CLK;
+            }
+        }
+        self.irq_flag = self.apu.irq();
+    }
+
     pub fn trigger_brk(&mut self) {
         self.brk_flag = true;
     }
@@ -193,8 +231,8 @@ impl Bus {
             self.interrupt(0xFFFA, false);
             self.nmi_flag = false;
         } else if !self.cpu.mask_interrupt() {
-            if self.irq_flag {
-                self.interrupt(0xFFFC, false);
+            if self.irq_flag || self.rom.mapper().irq() {
+                self.interrupt(0xFFFE, false);
                 self.irq_flag = false;
             } else if self.brk_flag {
                 self.interrupt(0xFFFE, true);
@@ -225,6 +263,18 @@ impl Bus {
         }
     }
 
+    pub fn controller_mut(&mut self) -> &mut Controller {
+        &mut self.controller
+    }
+
+    pub fn apu_mut(&mut self) -> &mut APU {
+        &mut self.apu
+    }
+
+    pub fn rom_mut(&mut self) -> &mut Rom {
+        &mut self.rom
+    }
+
     pub fn cpu(&self) -> &CPU {
         &self.cpu
     }
@@ -238,6 +288,43 @@ impl Bus {
     }
 }
 
+impl Stateful for Bus {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.cpu.save_state(w);
+        self.memory.save_state(w);
+        self.ppu.save_state(w);
+        self.apu.save_state(w);
+        self.controller.save_state(w);
+        self.rom.save_state(w);
+
+        w.write_bool(self.nmi_flag);
+        w.write_bool(self.brk_flag);
+        w.write_bool(self.irq_flag);
+        w.write_u16(self.dma_clk);
+        w.write_u8(self.open_bus);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.cpu.load_state(r)?;
+        self.memory.load_state(r)?;
+        self.ppu.load_state(r)?;
+        self.apu.load_state(r)?;
+        self.controller.load_state(r)?;
+        self.rom.load_state(r)?;
+
+        self.nmi_flag = r.read_bool()?;
+        self.brk_flag = r.read_bool()?;
+        self.irq_flag = r.read_bool()?;
+        self.dma_clk = r.read_u16()?;
+        self.open_bus = r.read_u8()?;
+        if r.finished() {
+            Ok(())
+        } else {
+            Err(Message::MalformedState)
+        }
+    }
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -247,4 +334,82 @@ pub mod tests {
         let rom = crate::rom::tests::mock();
         Bus::new(rom, None, &conf)
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_open_bus() {
+        let mut bus = mock();
+        bus.write(0x0010, 0x5A);
+        assert_eq!(bus.read(0x0010), 0x5A);
+        assert_eq!(bus.read(0x4018), 0x5A);
+        assert_eq!(bus.read(0x5000), 0x5A);
+        assert_eq!(bus.read(0x6000), 0x5A);
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let mut bus = mock();
+        bus.write(0x0123, 0x45);
+        bus.write(0x2006, 0x3F);
+        bus.write(0x2006, 0x01);
+        bus.write(0x2007, 0x16);
+        bus.cpu_mut().set_p(0xC3);
+        bus.irq_flag = true;
+        let mut w = StateWriter::new();
+        bus.save_state(&mut w);
+        let data = w.finish();
+
+        let mut other = mock();
+        let mut r = StateReader::new(&data).ok().unwrap();
+        assert!(other.load_state(&mut r).is_ok());
+        let mut w = StateWriter::new();
+        other.save_state(&mut w);
+        assert_eq!(w.finish(), data);
+
+        assert_eq!(other.read(0x0123), 0x45);
+        assert_eq!(other.cpu().p(), bus.cpu().p());
+        assert!(other.irq_flag);
+    }
+
+    #[test]
+    fn test_state_truncated() {
+        let bus = mock();
+        let mut w = StateWriter::new();
+        bus.save_state(&mut w);
+        let data = w.finish();
+
+        let mut other = mock();
+        let mut r = StateReader::new(&data[..data.len() - 1]).ok().unwrap();
+        assert!(other.load_state(&mut r).is_err());
+    }
+
+    #[test]
+    fn test_dmc() {
+        let mut bus = mock();
+        // A sample of 1 byte at $C000 with the interrupt
+        bus.write(0x4010, 0x8F);
+        bus.write(0x4012, 0x00);
+        bus.write(0x4013, 0x00);
+        bus.write(0x4015, 0x10);
+        assert_eq!(bus.read(0x4015), 0x10);
+        bus.apu_ticks(1);
+        assert_eq!(bus.check_dma(), 4);
+        assert!(bus.irq_flag);
+        assert_eq!(bus.read(0x4015), 0x80);
+        // Acknowledged by writing $4015
+        bus.write(0x4015, 0x00);
+        bus.apu_ticks(1);
+        assert!(!bus.irq_flag);
+        assert_eq!(bus.check_dma(), 0);
+    }
+
+    #[test]
+    fn test_controller_open_bus() {
+        let mut bus = mock();
+        bus.controller_mut().set_buttons(0, 0x01);
+        bus.write(0x4016, 1);
+        bus.write(0x4016, 0);
+        bus.write(0x0000, 0x40);
+        assert_eq!(bus.read(0x4016), 0x41);
+        assert_eq!(bus.read(0x4016), 0x40);
+    }
+}
@@ -1,17 +1,661 @@
-pub struct Controller {
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
+// The buttons of the standard controller, in the order they are reported
+pub const BUTTON_A: u8 = 0x01;
+pub const BUTTON_B: u8 = 0x02;
+pub const BUTTON_SELECT: u8 = 0x04;
+pub const BUTTON_START: u8 = 0x08;
+pub const BUTTON_UP: u8 = 0x10;
+pub const BUTTON_DOWN: u8 = 0x20;
+pub const BUTTON_LEFT: u8 = 0x40;
+pub const BUTTON_RIGHT: u8 = 0x80;
+
+// The devices plugged into the controller ports or the expansion port
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Device {
+    // Two standard controllers
+    Standard,
+    // The NES Four Score, player 3 and 4 are read after player 1 and 2
+    FourScore,
+    // The Famicom expansion port adapter, player 3 and 4 are read on bit 1
+    Famicom4P,
+    // The light gun on port 2, player 1 keeps the controller on port 1
+    Zapper,
+    // The Arkanoid paddle on port 2
+    Vaus,
+    // The Arkanoid paddle on the Famicom expansion port
+    VausFamicom,
+    // The Power Pad on port 2, side A and B differ in the layout of the buttons
+    PowerPadA,
+    PowerPadB,
+    // The Family Trainer mat on the Famicom expansion port, scanned by rows
+    FamilyTrainerA,
+    FamilyTrainerB,
+    // The Family BASIC keyboard on the Famicom expansion port
+    FamilyKeyboard
+}
 
+impl Device {
+    // Selects the device from the default expansion device in the NES 2.0 header
+    pub fn from_exp_device(code: u8) -> Device {
+        match code {
+            0x02 => Device::FourScore,
+            0x03 => Device::Famicom4P,
+            0x08 => Device::Zapper,
+            0x0F => Device::Vaus,
+            0x0B => Device::PowerPadA,
+            0x0C => Device::PowerPadB,
+            0x0D => Device::FamilyTrainerA,
+            0x0E => Device::FamilyTrainerB,
+            0x10 => Device::VausFamicom,
+            0x23 => Device::FamilyKeyboard,
+            _ => Device::Standard
+        }
+    }
+
+    // Whether the device has the mat buttons stepped on with the keyboard
+    pub fn has_mat(&self) -> bool {
+        matches!(self, Device::PowerPadA | Device::PowerPadB | Device::FamilyTrainerA | Device::FamilyTrainerB)
+    }
+}
+
+// The signatures reported by the Four Score on the 20th read of $4016 and the 19th read of $4017
+const FOUR_SCORE_SIGNATURES: [u32; 2] = [0x08, 0x04];
+
+// The Power Pad buttons reported on bit 3 and bit 4 of $4017
+const POWER_PAD_BIT_3: [u8; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const POWER_PAD_BIT_4: [u8; 4] = [4, 3, 12, 8];
+
+// The range of the paddle potentiometer from the left to the right
+const PADDLE_MIN: u8 = 98;
+const PADDLE_MAX: u8 = 242;
+
+pub struct Controller {
+    device: Device,
+    // Famicom controllers, the controller 2 has a microphone instead of Select and Start
+    famicom: bool,
+    mic: bool,
+    // The buttons pressed by player 1-4
+    buttons: [u8; 4],
+    // The shift registers latched from the buttons,
+    // bit 0 of $4016/$4017 are shifted out of 0 and 1, and bit 1 out of 2 and 3.
+    shift: [u32; 4],
+    strobe: bool,
+    // The NES pixel pointed by the mouse or the touch, None if out of the screen
+    pointer: Option<(u8, u8)>,
+    // The trigger of the Zapper
+    trigger: bool,
+    // Whether the Zapper senses the light at the pointer
+    light: bool,
+    // The paddle position and the shift register latched from it
+    paddle: u8,
+    paddle_shift: u8,
+    // The mat buttons, bit 0-11 for button 1-12
+    mat: u16,
+    // The shift registers of bit 3 and bit 4 latched from the Power Pad
+    mat_shift: [u32; 2],
+    // The rows of the Family Trainer deselected by $4016 bit 0-2
+    mat_rows: u8,
+    // The Family BASIC keyboard matrix, the row being scanned and the column selected
+    family_keys: [u8; 9],
+    family_row: u8,
+    family_column: u8,
+    family_enabled: bool
 }
 
 impl Controller {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(device: Device) -> Self {
+        Self {
+            device,
+            famicom: false,
+            mic: false,
+            buttons: [0; 4],
+            shift: [0; 4],
+            strobe: false,
+            pointer: None,
+            trigger: false,
+            light: false,
+            paddle: PADDLE_MIN,
+            paddle_shift: 0,
+            mat: 0,
+            mat_shift: [0; 2],
+            mat_rows: 0x07,
+            family_keys: [0; 9],
+            family_row: 0,
+            family_column: 0,
+            family_enabled: false
+        }
+    }
+
+    pub fn device(&self) -> Device {
+        self.device
+    }
+
+    // Sets the buttons pressed by the player, 0-3 for player 1-4.
+    // Player 3 and 4 are ignored without a four-player adapter.
+    pub fn set_buttons(&mut self, player: usize, buttons: u8) {
+        let buttons = if self.famicom && player == 1 {
+            buttons & !(BUTTON_SELECT | BUTTON_START)
+        } else {
+            buttons
+        };
+        if let Some(b) = self.buttons.get_mut(player) {
+            *b = buttons;
+        }
+    }
+
+    pub fn set_famicom(&mut self, famicom: bool) {
+        self.famicom = famicom;
+    }
+
+    pub fn set_mic(&mut self, on: bool) {
+        self.mic = on;
+    }
+
+    // Sets the pointed NES pixel and whether the pointer is pressed,
+    // the paddle stays where it is when the pointer leaves the screen.
+    pub fn set_pointer(&mut self, pointer: Option<(u8, u8)>, pressed: bool) {
+        self.pointer = pointer;
+        self.trigger = pressed;
+        if let Some((x, _)) = pointer {
+            self.paddle = PADDLE_MIN + (x as u16 * (PADDLE_MAX - PADDLE_MIN) as u16 / 255) as u8;
+        }
+    }
+
+    // Sets the mat buttons from left to right and top to bottom as the player sees.
+    // Side A is the back of side B, so the buttons are mirrored.
+    pub fn set_mat(&mut self, mat: u16) {
+        self.mat = match self.device {
+            Device::PowerPadA | Device::FamilyTrainerA => (0..12)
+                .filter(|i| mat & (1 << i) != 0)
+                .fold(0, |m, i| m | 1 << (i / 4 * 4 + 3 - i % 4)),
+            _ => mat
+        };
+    }
+
+    pub fn set_family_keys(&mut self, keys: &[u8; 9]) {
+        self.family_keys = *keys;
+    }
+
+    // The pixel where the light is sensed, None if no light gun is aiming at the screen
+    pub fn light_target(&self) -> Option<(u8, u8)> {
+        match self.device {
+            Device::Zapper => self.pointer,
+            _ => None
+        }
+    }
+
+    pub fn set_light(&mut self, light: bool) {
+        self.light = light;
+    }
+
+    // Returns the bit 0-4 of $4016/$4017, the upper bits are left to the open bus
+    pub fn read_joy(&mut self, index: u8) -> u8 {
+        let index = index as usize & 0x01;
+        if self.strobe {
+            // The shift registers keep reloading while the strobe is high
+            self.latch();
+        }
+        let trigger = self.trigger as u8;
+        // The microphone is reported on bit 2 of $4016
+        let mic = if self.famicom && self.mic && index == 0 { 0x04 } else { 0 };
+        mic | match (self.device, index) {
+            (Device::Zapper, 1) => self.read_zapper(),
+            // Bit 3 is the fire button, and bit 4 is the paddle position
+            (Device::Vaus, 1) => (self.read_paddle() << 4) | (trigger << 3),
+            (Device::VausFamicom, 0) => self.read_serial(0) | (trigger << 1),
+            (Device::VausFamicom, 1) => self.read_serial(1) | (self.read_paddle() << 1),
+            (Device::PowerPadA, 1) | (Device::PowerPadB, 1) => self.read_power_pad(),
+            (Device::FamilyTrainerA, 1) | (Device::FamilyTrainerB, 1) => self.read_serial(1) | self.read_family_trainer(),
+            (Device::FamilyKeyboard, 1) => self.read_serial(1) | self.read_family_keyboard(),
+            _ => self.read_serial(index)
+        }
+    }
+
+    pub fn write_joy_strode(&mut self, v: u8) {
+        self.mat_rows = v & 0x07;
+        self.write_family_keyboard(v);
+        self.strobe = v & 0x01 != 0;
+        if self.strobe {
+            self.latch();
+        }
+    }
+
+    // Shifts out bit 0 and bit 1 of the port
+    fn read_serial(&mut self, index: usize) -> u8 {
+        let v = (self.shift[index] & 0x01) | (self.shift[index + 2] & 0x01) << 1;
+        if !self.strobe {
+            // Official controllers report 1 after all the buttons are read
+            self.shift[index] = (self.shift[index] >> 1) | 0x8000_0000;
+            self.shift[index + 2] = (self.shift[index + 2] >> 1) | 0x8000_0000;
+        }
+        v as u8
+    }
+
+    // The paddle position is shifted out from the most significant bit and inverted
+    fn read_paddle(&mut self) -> u8 {
+        let v = self.paddle_shift >> 7;
+        if !self.strobe {
+            self.paddle_shift <<= 1;
+        }
+        v
+    }
+
+    // Bit 3 and 4 are shifted out, 1 for the pressed buttons
+    fn read_power_pad(&mut self) -> u8 {
+        let v = ((self.mat_shift[0] & 0x01) << 3) | ((self.mat_shift[1] & 0x01) << 4);
+        if !self.strobe {
+            self.mat_shift[0] = (self.mat_shift[0] >> 1) | 0x8000_0000;
+            self.mat_shift[1] = (self.mat_shift[1] >> 1) | 0x8000_0000;
+        }
+        v as u8
+    }
+
+    // Bit 1-4 are the buttons in the selected rows from right to left, 0 for the pressed ones.
+    // Bit 2 of $4016 selects button 1-4, bit 1 selects 5-8 and bit 0 selects 9-12.
+    fn read_family_trainer(&self) -> u8 {
+        let mut v = 0x1E;
+        for row in 0..3 {
+            if self.mat_rows & (0x04 >> row) == 0 {
+                for col in 0..4 {
+                    if self.mat & (1 << (row * 4 + col)) != 0 {
+                        v &= !(0x10 >> col);
+                    }
+                }
+            }
+        }
+        v
+    }
+
+    // Bit 0 resets the scan to row 0, bit 1 selects the column
+    // and the next row is selected when the column goes back to 0, bit 2 enables the keyboard.
+    fn write_family_keyboard(&mut self, v: u8) {
+        let column = (v >> 1) & 0x01;
+        if v & 0x01 != 0 {
+            self.family_row = 0;
+        } else if self.family_column == 1 && column == 0 {
+            self.family_row = self.family_row.saturating_add(1);
+        }
+        self.family_column = column;
+        self.family_enabled = v & 0x04 != 0;
+    }
+
+    // Bit 1-4 are the keys in the selected column of the row, 0 for the pressed ones
+    fn read_family_keyboard(&self) -> u8 {
+        if !self.family_enabled {
+            return 0;
+        }
+        match self.family_keys.get(self.family_row as usize) {
+            Some(keys) => {
+                let keys = (keys >> (4 * self.family_column)) & 0x0F;
+                (!keys & 0x0F) << 1
+            },
+            None => 0x1E
+        }
+    }
+
+    // Bit 3 is 0 when the light is sensed, and bit 4 is 1 when the trigger is pulled
+    fn read_zapper(&self) -> u8 {
+        let light = if self.light && self.pointer.is_some() { 0 } else { 0x08 };
+        let trigger = if self.trigger { 0x10 } else { 0 };
+        light | trigger
+    }
+
+    fn latch(&mut self) {
+        let b = self.buttons.map(|b| b as u32);
+        self.paddle_shift = !self.paddle;
+        let mat = |buttons: &[u8]| buttons.iter().enumerate()
+            .fold(0u32, |v, (i, n)| v | (((self.mat >> (n - 1)) & 0x01) as u32) << i);
+        self.mat_shift = [
+            mat(&POWER_PAD_BIT_3) | 0xFFFF_FF00,
+            mat(&POWER_PAD_BIT_4) | 0xFFFF_FFF0
+        ];
+        // The unused bits read as 1 after the reported ones
+        self.shift = match self.device {
+            Device::Standard | Device::Zapper | Device::VausFamicom
+                | Device::FamilyTrainerA | Device::FamilyTrainerB | Device::FamilyKeyboard => [b[0] | 0xFFFF_FF00, b[1] | 0xFFFF_FF00, 0, 0],
+            // Nothing but the paddle is on port 2
+            Device::Vaus | Device::PowerPadA | Device::PowerPadB => [b[0] | 0xFFFF_FF00, 0, 0, 0],
+            Device::FourScore => [
+                b[0] | b[2] << 8 | FOUR_SCORE_SIGNATURES[0] << 16 | 0xFF00_0000,
+                b[1] | b[3] << 8 | FOUR_SCORE_SIGNATURES[1] << 16 | 0xFF00_0000,
+                0, 0
+            ],
+            Device::Famicom4P => [
+                b[0] | 0xFFFF_FF00, b[1] | 0xFFFF_FF00,
+                b[2] | 0xFFFF_FF00, b[3] | 0xFFFF_FF00
+            ]
+        };
+    }
+}
+
+impl Stateful for Controller {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.buttons);
+        for shift in self.shift.iter() {
+            w.write_u32(*shift);
+        }
+        w.write_bool(self.strobe);
+        let (x, y) = self.pointer.unwrap_or((0, 0));
+        w.write_bool(self.pointer.is_some());
+        w.write_u8(x);
+        w.write_u8(y);
+        w.write_bool(self.trigger);
+        w.write_bool(self.light);
+        w.write_u8(self.paddle);
+        w.write_u8(self.paddle_shift);
+        w.write_u16(self.mat);
+        w.write_u32(self.mat_shift[0]);
+        w.write_u32(self.mat_shift[1]);
+        w.write_u8(self.mat_rows);
+        w.write_bool(self.mic);
+        w.write_bytes(&self.family_keys);
+        w.write_u8(self.family_row);
+        w.write_u8(self.family_column);
+        w.write_bool(self.family_enabled);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        r.read_bytes(&mut self.buttons)?;
+        for shift in self.shift.iter_mut() {
+            *shift = r.read_u32()?;
+        }
+        self.strobe = r.read_bool()?;
+        let pointed = r.read_bool()?;
+        let point = (r.read_u8()?, r.read_u8()?);
+        self.pointer = if pointed { Some(point) } else { None };
+        self.trigger = r.read_bool()?;
+        self.light = r.read_bool()?;
+        self.paddle = r.read_u8()?;
+        self.paddle_shift = r.read_u8()?;
+        self.mat = r.read_u16()?;
+        self.mat_shift = [r.read_u32()?, r.read_u32()?];
+        self.mat_rows = r.read_u8()?;
+        self.mic = r.read_bool()?;
+        r.read_bytes(&mut self.family_keys)?;
+        self.family_row = r.read_u8()?;
+        self.family_column = r.read_u8()?;
+        self.family_enabled = r.read_bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(controller: &mut Controller, index: u8) -> u8 {
+        let mut v = 0;
+        for i in 0..8 {
+            v |= controller.read_joy(index) << i;
+        }
+        v
+    }
+
+    #[test]
+    fn test_serial_read() {
+        let mut controller = Controller::new(Device::Standard);
+        controller.set_buttons(0, BUTTON_A | BUTTON_START | BUTTON_RIGHT);
+        controller.set_buttons(1, BUTTON_B | BUTTON_UP);
+        controller.write_joy_strode(1);
+        controller.write_joy_strode(0);
+
+        assert_eq!(read_all(&mut controller, 0), BUTTON_A | BUTTON_START | BUTTON_RIGHT);
+        assert_eq!(read_all(&mut controller, 1), BUTTON_B | BUTTON_UP);
+        assert_eq!(controller.read_joy(0), 1);
+        assert_eq!(controller.read_joy(1), 1);
+    }
+
+    #[test]
+    fn test_latch() {
+        let mut controller = Controller::new(Device::Standard);
+        controller.set_buttons(0, BUTTON_SELECT);
+        controller.write_joy_strode(1);
+        controller.write_joy_strode(0);
+        // Changes after the latch are not reported until the next strobe
+        controller.set_buttons(0, BUTTON_DOWN);
+        assert_eq!(read_all(&mut controller, 0), BUTTON_SELECT);
+
+        controller.write_joy_strode(1);
+        controller.write_joy_strode(0);
+        assert_eq!(read_all(&mut controller, 0), BUTTON_DOWN);
+    }
+
+    #[test]
+    fn test_strobe_high() {
+        let mut controller = Controller::new(Device::Standard);
+        controller.set_buttons(0, BUTTON_A | BUTTON_B);
+        controller.write_joy_strode(1);
+        for _ in 0..10 {
+            assert_eq!(controller.read_joy(0), 1);
+        }
+        controller.set_buttons(0, BUTTON_B);
+        assert_eq!(controller.read_joy(0), 0);
+    }
+
+    #[test]
+    fn test_four_score() {
+        let mut controller = Controller::new(Device::FourScore);
+        controller.set_buttons(0, BUTTON_A);
+        controller.set_buttons(1, BUTTON_B);
+        controller.set_buttons(2, BUTTON_START);
+        controller.set_buttons(3, BUTTON_RIGHT);
+        controller.write_joy_strode(1);
+        controller.write_joy_strode(0);
+
+        assert_eq!(read_all(&mut controller, 0), BUTTON_A);
+        assert_eq!(read_all(&mut controller, 0), BUTTON_START);
+        assert_eq!(read_all(&mut controller, 0), 0x08);
+        assert_eq!(read_all(&mut controller, 0), 0xFF);
+        assert_eq!(read_all(&mut controller, 1), BUTTON_B);
+        assert_eq!(read_all(&mut controller, 1), BUTTON_RIGHT);
+        assert_eq!(read_all(&mut controller, 1), 0x04);
+    }
+
+    #[test]
+    fn test_famicom_4p() {
+        let mut controller = Controller::new(Device::Famicom4P);
+        controller.set_buttons(0, BUTTON_A);
+        controller.set_buttons(2, BUTTON_A | BUTTON_B);
+        controller.set_buttons(3, BUTTON_B);
+        controller.write_joy_strode(1);
+        controller.write_joy_strode(0);
+
+        assert_eq!(controller.read_joy(0), 0x03);
+        assert_eq!(controller.read_joy(0), 0x02);
+        assert_eq!(controller.read_joy(1), 0x00);
+        assert_eq!(controller.read_joy(1), 0x02);
+    }
+
+    #[test]
+    fn test_standard_ignores_player_3() {
+        let mut controller = Controller::new(Device::Standard);
+        controller.set_buttons(2, BUTTON_A);
+        controller.write_joy_strode(1);
+        controller.write_joy_strode(0);
+        assert_eq!(controller.read_joy(0), 0);
+        for _ in 0..8 {
+            controller.read_joy(0);
+        }
+        assert_eq!(controller.read_joy(0), 1);
     }
 
-    pub fn read_joy(&self, _index: u8) -> u8 {
-        0
+    #[test]
+    fn test_zapper() {
+        let mut controller = Controller::new(Device::Zapper);
+        controller.set_buttons(0, BUTTON_A);
+        controller.write_joy_strode(1);
+        controller.write_joy_strode(0);
+        assert_eq!(controller.read_joy(0), 1);
+        assert_eq!(controller.light_target(), None);
+        assert_eq!(controller.read_joy(1), 0x08);
+
+        controller.set_pointer(Some((10, 20)), true);
+        assert_eq!(controller.light_target(), Some((10, 20)));
+        assert_eq!(controller.read_joy(1), 0x18);
+        controller.set_light(true);
+        assert_eq!(controller.read_joy(1), 0x10);
+
+        // No light is sensed when aiming out of the screen
+        controller.set_pointer(None, false);
+        assert_eq!(controller.read_joy(1), 0x08);
+        assert_eq!(Controller::new(Device::Standard).light_target(), None);
     }
 
-    pub fn write_joy_strode(&mut self, _v: u8) {
+    fn read_paddle(controller: &mut Controller, index: u8, bit: u8) -> u8 {
+        let mut v = 0;
+        for _ in 0..8 {
+            v = (v << 1) | ((controller.read_joy(index) >> bit) & 0x01);
+        }
+        !v
+    }
 
+    #[test]
+    fn test_vaus() {
+        let mut controller = Controller::new(Device::Vaus);
+        controller.set_pointer(Some((0, 100)), false);
+        controller.write_joy_strode(1);
+        controller.write_joy_strode(0);
+        assert_eq!(read_paddle(&mut controller, 1, 4), PADDLE_MIN);
+
+        controller.set_pointer(Some((255, 100)), true);
+        controller.write_joy_strode(1);
+        controller.write_joy_strode(0);
+        assert_eq!(controller.read_joy(1) & 0x08, 0x08);
+        controller.write_joy_strode(1);
+        controller.write_joy_strode(0);
+        assert_eq!(read_paddle(&mut controller, 1, 4), PADDLE_MAX);
+
+        // The paddle stays when the pointer leaves
+        controller.set_pointer(None, false);
+        controller.write_joy_strode(1);
+        controller.write_joy_strode(0);
+        assert_eq!(controller.read_joy(1) & 0x08, 0);
+        assert_eq!(controller.paddle, PADDLE_MAX);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_vaus_famicom() {
+        let mut controller = Controller::new(Device::VausFamicom);
+        controller.set_buttons(0, BUTTON_A);
+        controller.set_pointer(Some((128, 0)), true);
+        controller.write_joy_strode(1);
+        controller.write_joy_strode(0);
+        assert_eq!(controller.read_joy(0), 0x03);
+        assert_eq!(controller.read_joy(0), 0x02);
+        assert_eq!(read_paddle(&mut controller, 1, 1), 170);
+    }
+
+    #[test]
+    fn test_power_pad() {
+        let mut controller = Controller::new(Device::PowerPadB);
+        // Button 1, 3 and 12
+        controller.set_mat(0x805);
+        controller.write_joy_strode(1);
+        controller.write_joy_strode(0);
+        let reads: Vec<u8> = (0..9).map(|_| controller.read_joy(1)).collect();
+        let bit_4: Vec<u8> = reads.iter().map(|v| (v >> 4) & 0x01).collect();
+        let bit_3: Vec<u8> = reads.iter().map(|v| (v >> 3) & 0x01).collect();
+        assert_eq!(bit_3, [0, 1, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(bit_4, [0, 1, 1, 0, 1, 1, 1, 1, 1]);
+        assert_eq!(reads[0] & 0x07, 0);
+    }
+
+    #[test]
+    fn test_power_pad_side_a() {
+        let mut controller = Controller::new(Device::PowerPadA);
+        controller.set_mat(0x001);
+        assert_eq!(controller.mat, 0x008);
+        controller.set_mat(0x110);
+        assert_eq!(controller.mat, 0x880);
+    }
+
+    #[test]
+    fn test_family_trainer() {
+        let mut controller = Controller::new(Device::FamilyTrainerB);
+        // Button 1, 6 and 12
+        controller.set_mat(0x821);
+        controller.write_joy_strode(0x03);
+        assert_eq!(controller.read_joy(1), 0x0E);
+        controller.write_joy_strode(0x05);
+        assert_eq!(controller.read_joy(1), 0x16);
+        controller.write_joy_strode(0x06);
+        assert_eq!(controller.read_joy(1), 0x1C);
+        controller.write_joy_strode(0x07);
+        assert_eq!(controller.read_joy(1), 0x1E);
+    }
+
+    #[test]
+    fn test_famicom() {
+        let mut controller = Controller::new(Device::Standard);
+        controller.set_famicom(true);
+        controller.set_buttons(0, BUTTON_SELECT | BUTTON_START);
+        controller.set_buttons(1, BUTTON_A | BUTTON_SELECT | BUTTON_START);
+        controller.write_joy_strode(1);
+        controller.write_joy_strode(0);
+        assert_eq!(read_all(&mut controller, 0), BUTTON_SELECT | BUTTON_START);
+        assert_eq!(read_all(&mut controller, 1), BUTTON_A);
+
+        controller.set_mic(true);
+        assert_eq!(controller.read_joy(0) & 0x04, 0x04);
+        assert_eq!(controller.read_joy(1) & 0x04, 0);
+        controller.set_mic(false);
+        assert_eq!(controller.read_joy(0) & 0x04, 0);
+
+        // The microphone is absent on NES
+        let mut controller = Controller::new(Device::Standard);
+        controller.set_mic(true);
+        assert_eq!(controller.read_joy(0) & 0x04, 0);
+    }
+
+    #[test]
+    fn test_family_keyboard() {
+        let mut controller = Controller::new(Device::FamilyKeyboard);
+        let mut keys = [0u8; 9];
+        // ']' on row 0, 'P' on row 2 column 1, and space on row 8 column 1
+        keys[0] = 0x01;
+        keys[2] = 0x20;
+        keys[8] = 0x40;
+        controller.set_family_keys(&keys);
+
+        assert_eq!(controller.read_joy(1), 0);
+        controller.write_joy_strode(0x05);
+        controller.write_joy_strode(0x04);
+        let mut rows = Vec::new();
+        for _ in 0..10 {
+            let low = controller.read_joy(1) & 0x1E;
+            controller.write_joy_strode(0x06);
+            let high = controller.read_joy(1) & 0x1E;
+            controller.write_joy_strode(0x04);
+            rows.push((low, high));
+        }
+        assert_eq!(rows[0], (0x1C, 0x1E));
+        assert_eq!(rows[1], (0x1E, 0x1E));
+        assert_eq!(rows[2], (0x1E, 0x1A));
+        assert_eq!(rows[8], (0x1E, 0x16));
+        assert_eq!(rows[9], (0x1E, 0x1E));
+
+        // Reset to row 0
+        controller.write_joy_strode(0x05);
+        controller.write_joy_strode(0x04);
+        assert_eq!(controller.read_joy(1) & 0x1E, 0x1C);
+    }
+
+    #[test]
+    fn test_exp_device() {
+        assert_eq!(Device::from_exp_device(0x00), Device::Standard);
+        assert_eq!(Device::from_exp_device(0x01), Device::Standard);
+        assert_eq!(Device::from_exp_device(0x02), Device::FourScore);
+        assert_eq!(Device::from_exp_device(0x03), Device::Famicom4P);
+        assert_eq!(Device::from_exp_device(0x08), Device::Zapper);
+        assert_eq!(Device::from_exp_device(0x0F), Device::Vaus);
+        assert_eq!(Device::from_exp_device(0x10), Device::VausFamicom);
+        assert_eq!(Device::from_exp_device(0x0B), Device::PowerPadA);
+        assert_eq!(Device::from_exp_device(0x0E), Device::FamilyTrainerB);
+        assert!(Device::PowerPadB.has_mat());
+        assert!(Device::FamilyTrainerA.has_mat());
+        assert!(!Device::Standard.has_mat());
+        assert_eq!(Device::from_exp_device(0x23), Device::FamilyKeyboard);
+    }
+}
@@ -1,3 +1,6 @@
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
 #[allow(non_snake_case)]
 pub struct CPU {
     A: u8,
@@ -328,6 +331,27 @@ impl CPU {
     }
 }
 
+impl Stateful for CPU {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.A);
+        w.write_u8(self.X);
+        w.write_u8(self.Y);
+        w.write_u8(self.S);
+        w.write_u16(self.PC);
+        w.write_u8(self.P.get());
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.A = r.read_u8()?;
+        self.X = r.read_u8()?;
+        self.Y = r.read_u8()?;
+        self.S = r.read_u8()?;
+        self.PC = r.read_u16()?;
+        self.P.set(r.read_u8()?);
+        Ok(())
+    }
+}
+
 #[allow(non_snake_case)]
 struct Flags {
     pub N: bool,
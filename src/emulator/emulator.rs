@@ -1,70 +1,133 @@
-use wasm_bindgen::prelude::{wasm_bindgen, Closure};
-use web_sys::{CanvasRenderingContext2d, ImageData, window};
+use wasm_bindgen::prelude::{wasm_bindgen, JsValue};
+use web_sys::{CanvasRenderingContext2d, ImageData};
 use crate::conf::Configuration;
 use crate::i18n::Message;
 use crate::rom::{Rom, Timing};
 use super::bus::Bus;
+use super::input::{Input, PLAYERS};
+use super::gamepad::Gamepads;
+use super::microphone::Microphone;
+use super::audio::Audio;
+use super::pacer::{Pacer, Pacing};
 use crate::emulator::instruction::{InstructionSet, Instruction};
-use js_sys::Function;
-use wasm_bindgen::{JsCast, Clamped};
+use js_sys::Uint8Array;
+use wasm_bindgen::Clamped;
 use js_sys::Math::random;
 use crate::init::{RAW_WIDTH, RAW_HEIGHT};
+use crate::state::{Stateful, StateWriter, StateReader};
+use std::rc::Rc;
+use std::cell::RefCell;
+
+// The battery RAM is checked for the auto-save callback once every this number of frames
+const AUTO_SAVE_FRAMES: u32 = 60;
 
 #[wasm_bindgen]
 pub struct Emulator {
     conf: Configuration,
     screen: CanvasRenderingContext2d,
-    frame: Option<Closure<dyn FnMut()>>,
-    timer: Option<i32>
+    bus: Option<Rc<RefCell<Bus>>>,
+    // Battery RAM imported before the cartridge is inserted
+    battery_ram: Option<Box<[u8]>>,
+    input: Rc<RefCell<Input>>,
+    pacer: Option<Pacer>
 }
 
 #[wasm_bindgen]
 impl Emulator {
     pub fn insert(&mut self, cartridge: Box<[u8]>) {
-        if self.frame.is_some() {
+        if self.pacer.is_some() {
             panic!("{}", self.conf.i18n().to_string(Message::CartridgeAlreadyInserted));
         }
 
-        let rom = Rom::parse(cartridge).map_err(|e| {
+        let mut rom = Rom::parse(cartridge, self.conf.bus_conflicts()).map_err(|e| {
             self.conf.i18n().to_string(e)
         }).unwrap();
+        if let (Some(data), Some(ram)) = (self.battery_ram.take(), rom.battery_ram_mut()) {
+            ram.load(&data);
+        }
         let fps = make_fps(&self.conf, &rom);
         let mut bus = Bus::new(rom, Some(self.screen.clone()), &self.conf);
+        self.input.borrow_mut().set_device(bus.controller_mut().device());
         let inst = InstructionSet::new();
 
         wait_ppu(&mut bus, &inst);
 
-        let frame = make_frame(bus, inst);
-        self.frame = Some(frame);
-        let timer = window().unwrap()
-            .set_interval_with_callback_and_timeout_and_arguments_0(
-                self.frame.as_ref().unwrap().as_ref().unchecked_ref::<Function>(),
-                (1000f64 / fps).floor() as i32
-            ).unwrap();
-        self.timer = Some(timer);
+        // The emulator runs without audio if Web Audio is not available
+        let audio = if self.conf.mute() {
+            None
+        } else {
+            Audio::new(self.conf.volume()).ok().map(|a| Rc::new(RefCell::new(a)))
+        };
+        if let Some(a) = audio.as_ref() {
+            bus.apu_mut().set_sample_rate(a.borrow().sample_rate());
+        }
+
+        let bus = Rc::new(RefCell::new(bus));
+        let frame = make_frame(bus.clone(), inst, self.input.clone(), audio.clone(), &self.conf);
+        self.bus = Some(bus);
+        self.pacer = Some(Pacer::start(self.conf.pacing(), fps, frame, audio));
     }
 
     pub fn stop(&mut self) {
-        match self.timer {
-            Some(timer) => {
-                window().unwrap().clear_interval_with_handle(timer);
-                self.timer = None;
+        self.pacer = None;
+    }
+
+    // Returns the battery RAM of the last inserted cartridge,
+    // or undefined if there's no cartridge or the cartridge has no battery.
+    pub fn export_battery_ram(&self) -> Option<Box<[u8]>> {
+        let mut bus = self.bus.as_ref()?.borrow_mut();
+        bus.rom_mut().battery_ram_mut().map(|ram| Box::from(ram.data()))
+    }
+
+    // Loads the battery RAM into the running game,
+    // or keeps it for the next cartridge if no game is running.
+    pub fn import_battery_ram(&mut self, data: Box<[u8]>) {
+        match self.bus.as_ref() {
+            Some(bus) if self.pacer.is_some() => {
+                if let Some(ram) = bus.borrow_mut().rom_mut().battery_ram_mut() {
+                    ram.load(&data);
+                }
             },
-            None => ()
+            _ => self.battery_ram = Some(data)
         }
-        self.frame = None;
+    }
+
+    // Sets the buttons pressed on the controller of the player 1-4,
+    // bit 0-7 are A, B, Select, Start, Up, Down, Left and Right.
+    // They are combined with the keys pressed on the keyboard.
+    pub fn set_buttons(&mut self, player: u8, buttons: u8) {
+        self.input.borrow_mut().set_buttons((player as usize).saturating_sub(1), buttons);
+    }
+
+    // Returns the state of the whole machine, or undefined if there's no cartridge
+    pub fn save_state(&self) -> Option<Box<[u8]>> {
+        let bus = self.bus.as_ref()?.borrow();
+        let mut w = StateWriter::new();
+        bus.save_state(&mut w);
+        Some(w.finish())
+    }
+
+    // Restores the machine from the state saved with the same cartridge
+    pub fn load_state(&mut self, data: Box<[u8]>) -> Result<(), JsValue> {
+        let mut bus = self.bus.as_ref()
+            .ok_or_else(|| JsValue::from_str(self.conf.i18n().to_string(Message::NoCartridge).as_str()))?
+            .borrow_mut();
+        let mut backup = StateWriter::new();
+        bus.save_state(&mut backup);
+
+        let result = StateReader::new(&data).and_then(|mut r| bus.load_state(&mut r));
+        result.map_err(|e| {
+            // The state may be partially loaded, so the machine is restored
+            let backup = backup.finish();
+            let _ = StateReader::new(&backup).and_then(|mut r| bus.load_state(&mut r));
+            JsValue::from_str(self.conf.i18n().to_string(e).as_str())
+        })
     }
 }
 
 fn make_fps(conf: &Configuration, rom: &Rom) -> f64 {
     match conf.fps() {
-        Some(f) => if f < 30f64 {
-            30f64
-        } else if f > 100f64 {
-            100f64
-        } else {
-            f
-        },
+        Some(f) => limit_fps(f, conf.pacing()),
         None => match rom.timing() {
             Timing::NTSC => 60.0988,
             Timing::PAL | Timing::Dendy => 50.007,
@@ -73,6 +136,15 @@ fn make_fps(conf: &Configuration, rom: &Rom) -> f64 {
     }
 }
 
+// The interval timer is kept within 30-100 fps, where its period in whole ms is accurate enough.
+// The animation frames run any positive fps, limited by the frames run in an animation frame.
+fn limit_fps(fps: f64, pacing: Pacing) -> f64 {
+    match pacing {
+        Pacing::Interval => fps.clamp(30f64, 100f64),
+        Pacing::AnimationFrame | Pacing::Audio => fps.max(1f64)
+    }
+}
+
 fn current_instruction<'a>(bus: &mut Bus, instructions: &'a InstructionSet) -> &'a Instruction {
     let pc = bus.cpu().pc();
     let code = bus.read(pc);
@@ -89,32 +161,100 @@ fn wait_ppu(bus: &mut Bus, instructions: &InstructionSet) {
     bus.ppu_ready();
 }
 
-fn make_frame(mut bus: Bus, instructions: InstructionSet) -> Closure<dyn FnMut()> {
-    Closure::wrap(Box::new(move || {
-        loop {
-            let dma_clk = bus.check_dma();
-            let finish = if dma_clk > 0 {
-                bus.ppu_ticks(3 * dma_clk)
+fn make_frame(bus: Rc<RefCell<Bus>>, instructions: InstructionSet,
+              input: Rc<RefCell<Input>>, audio: Option<Rc<RefCell<Audio>>>,
+              conf: &Configuration) -> Box<dyn FnMut()> {
+    let on_save = conf.on_save().cloned();
+    let gamepad_map = conf.gamepad().clone();
+    let mut gamepads = Gamepads::new();
+    let mut microphone = if conf.microphone() {
+        Some(Microphone::new())
+    } else {
+        None
+    };
+    let mut frames: u32 = 0;
+    Box::new(move || {
+        // A timer faster than the audio skips frames, before the audio buffer overflows
+        if matches!(audio.as_ref(), Some(a) if a.borrow().overflowing()) {
+            return;
+        }
+        let saved = {
+            let mut bus = bus.borrow_mut();
+            if let Some(a) = audio.as_ref() {
+                bus.apu_mut().set_rate_control(a.borrow().rate_control());
+            }
+            {
+                let mut input = input.borrow_mut();
+                gamepads.poll(&gamepad_map, &mut input);
+                if let Some(m) = microphone.as_mut() {
+                    input.set_mic_input(m.blown());
+                }
+                for player in 0..PLAYERS {
+                    bus.controller_mut().set_buttons(player, input.buttons(player));
+                }
+                let pressed = input.take_pressed();
+                bus.controller_mut().set_pointer(input.pointer(), pressed);
+                bus.controller_mut().set_mat(input.mat());
+                bus.controller_mut().set_mic(input.mic());
+                bus.controller_mut().set_family_keys(input.family_keys());
+            }
+            loop {
+                let dma_clk = bus.check_dma();
+                let finish = if dma_clk > 0 {
+                    bus.apu_ticks(dma_clk);
+                    bus.ppu_ticks(3 * dma_clk)
+                } else {
+                    bus.check_interrupt();
+                    let inst = current_instruction(&mut bus, &instructions);
+                    let cpu_cycles = inst.apply(&mut bus);
+                    bus.apu_ticks(cpu_cycles);
+                    bus.ppu_ticks(3 * cpu_cycles)
+                };
+                if finish {
+                    break;
+                }
+            }
+            if let Some(a) = audio.as_ref() {
+                let mut a = a.borrow_mut();
+                bus.apu_mut().take_samples(a.samples_mut());
+                a.flush();
+            }
+
+            frames += 1;
+            if frames == AUTO_SAVE_FRAMES {
+                frames = 0;
+            }
+            if on_save.is_some() && frames == 0 {
+                match bus.rom_mut().battery_ram_mut() {
+                    Some(ram) => if ram.take_dirty() {
+                        Some(Uint8Array::from(ram.data()))
+                    } else {
+                        None
+                    },
+                    None => None
+                }
             } else {
-                bus.check_interrupt();
-                let inst = current_instruction(&mut bus, &instructions);
-                let cpu_cycles = inst.apply(&mut bus);
-                bus.ppu_ticks(3 * cpu_cycles)
-            };
-            if finish {
-                break;
+                None
             }
+        };
+
+        // The bus is released here, so the callback is free to call the emulator
+        if let (Some(f), Some(data)) = (on_save.as_ref(), saved) {
+            let _ = f.call1(&JsValue::NULL, &data);
         }
-    }) as Box<dyn FnMut()>)
+    })
 }
 
-pub fn new_emulator(ctx: CanvasRenderingContext2d, conf: Configuration) -> Emulator {
+pub fn new_emulator(ctx: CanvasRenderingContext2d, conf: Configuration,
+                    input: Rc<RefCell<Input>>) -> Emulator {
     draw_splash(&ctx, &conf);
     Emulator {
         conf,
         screen: ctx,
-        frame: None,
-        timer: None
+        bus: None,
+        battery_ram: None,
+        input,
+        pacer: None
     }
 }
 
@@ -138,4 +278,19 @@ fn draw_splash(ctx: &CanvasRenderingContext2d, _conf: &Configuration) {
         RAW_WIDTH as u32,
         RAW_HEIGHT as u32).unwrap();
     ctx.put_image_data(&image, 0.0, 0.0).unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limit_fps() {
+        assert_eq!(limit_fps(60.0, Pacing::Interval), 60.0);
+        assert_eq!(limit_fps(10.0, Pacing::Interval), 30.0);
+        assert_eq!(limit_fps(144.0, Pacing::Interval), 100.0);
+        assert_eq!(limit_fps(10.0, Pacing::AnimationFrame), 10.0);
+        assert_eq!(limit_fps(144.0, Pacing::Audio), 144.0);
+        assert_eq!(limit_fps(0.0, Pacing::AnimationFrame), 1.0);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,97 @@
+use wasm_bindgen::JsCast;
+use web_sys::{Gamepad, GamepadButton, window};
+use crate::conf::GamepadMap;
+use super::input::{Input, PLAYERS};
+
+// Assigns the connected gamepads to the players in the order they are plugged in,
+// so a player keeps the pad when others are unplugged.
+pub struct Gamepads {
+    // The browser index of the gamepad held by every player
+    slots: [Option<u32>; PLAYERS]
+}
+
+impl Gamepads {
+    pub fn new() -> Self {
+        Self {
+            slots: [None; PLAYERS]
+        }
+    }
+
+    // Frees the slots of the unplugged gamepads and assigns the new ones to the free slots,
+    // gamepads beyond the number of players are ignored.
+    fn update(&mut self, connected: &[u32]) {
+        for slot in self.slots.iter_mut() {
+            if let Some(index) = slot {
+                if !connected.contains(index) {
+                    *slot = None;
+                }
+            }
+        }
+        for index in connected {
+            if !self.slots.contains(&Some(*index)) {
+                if let Some(slot) = self.slots.iter_mut().find(|s| s.is_none()) {
+                    *slot = Some(*index);
+                }
+            }
+        }
+    }
+
+    fn player(&self, index: u32) -> Option<usize> {
+        self.slots.iter().position(|s| *s == Some(index))
+    }
+
+    // Reads the gamepads through the Gamepad API, which is polled rather than evented
+    pub fn poll(&mut self, map: &GamepadMap, input: &mut Input) {
+        let pads: Vec<Gamepad> = match window().and_then(|w| w.navigator().get_gamepads().ok()) {
+            Some(pads) => pads.iter()
+                .filter_map(|p| p.dyn_into::<Gamepad>().ok())
+                .filter(|p| p.connected())
+                .collect(),
+            None => Vec::new()
+        };
+        let connected: Vec<u32> = pads.iter().map(|p| p.index()).collect();
+        self.update(&connected);
+
+        let mut buttons = [0u8; PLAYERS];
+        for pad in pads.iter() {
+            if let Some(player) = self.player(pad.index()) {
+                let pad_buttons = pad.buttons();
+                let axes = pad.axes();
+                buttons[player] = map.buttons(
+                    |i| pad_buttons.get(i).dyn_into::<GamepadButton>()
+                        .map(|b| b.pressed())
+                        .unwrap_or(false),
+                    |i| axes.get(i).as_f64().unwrap_or(0.0)
+                );
+            }
+        }
+        for (player, b) in buttons.iter().enumerate() {
+            input.set_gamepad(player, *b);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hot_plug() {
+        let mut pads = Gamepads::new();
+        pads.update(&[0, 1]);
+        assert_eq!(pads.player(0), Some(0));
+        assert_eq!(pads.player(1), Some(1));
+
+        // Player 2 keeps the pad after the first one is unplugged
+        pads.update(&[1]);
+        assert_eq!(pads.player(0), None);
+        assert_eq!(pads.player(1), Some(1));
+
+        pads.update(&[1, 2, 3, 4, 5]);
+        assert_eq!(pads.player(2), Some(0));
+        assert_eq!(pads.player(1), Some(1));
+        assert_eq!(pads.player(3), Some(2));
+        assert_eq!(pads.player(4), Some(3));
+        assert_eq!(pads.player(5), None);
+    }
+}
@@ -0,0 +1,238 @@
+use super::controller::Device;
+
+// The number of players whose buttons are collected from the browser
+pub const PLAYERS: usize = 4;
+
+// The buttons pressed by the browser input devices,
+// which are applied to the controllers at the beginning of every frame.
+pub struct Input {
+    // The device of the inserted cartridge, which decides where the keys are routed
+    device: Device,
+    // Buttons pressed on the keyboard
+    keyboard: [u8; PLAYERS],
+    // The mat buttons pressed on the keyboard, bit 0-11 from left to right and top to bottom
+    mat: u16,
+    // The microphone turned on by the key or the browser microphone
+    mic_key: bool,
+    mic_input: bool,
+    // The rows of the Family BASIC keyboard matrix pressed on the keyboard
+    family_keys: [u8; 9],
+    // Buttons pressed on the gamepads
+    gamepad: [u8; PLAYERS],
+    // Buttons set through Emulator.set_buttons
+    external: [u8; PLAYERS],
+    // The NES pixel pointed by the mouse or the touch
+    pointer: Option<(u8, u8)>,
+    pressed: bool,
+    // Kept until the next frame, so a click shorter than a frame is not lost
+    clicked: bool
+}
+
+impl Input {
+    pub fn new() -> Self {
+        Self {
+            device: Device::Standard,
+            keyboard: [0; PLAYERS],
+            mat: 0,
+            mic_key: false,
+            mic_input: false,
+            family_keys: [0; 9],
+            gamepad: [0; PLAYERS],
+            external: [0; PLAYERS],
+            pointer: None,
+            pressed: false,
+            clicked: false
+        }
+    }
+
+    pub fn set_device(&mut self, device: Device) {
+        self.device = device;
+    }
+
+    pub fn device(&self) -> Device {
+        self.device
+    }
+
+    pub fn press_key(&mut self, player: usize, button: u8, pressed: bool) {
+        if player < PLAYERS {
+            if pressed {
+                self.keyboard[player] |= button;
+            } else {
+                self.keyboard[player] &= !button;
+            }
+        }
+    }
+
+    // Releases all the keys, since the key-up events are lost when the page loses focus
+    pub fn release_keys(&mut self) {
+        self.keyboard = [0; PLAYERS];
+        self.mat = 0;
+        self.mic_key = false;
+        self.family_keys = [0; 9];
+    }
+
+    pub fn press_family_key(&mut self, row: usize, bit: u8, pressed: bool) {
+        if let Some(r) = self.family_keys.get_mut(row) {
+            if pressed {
+                *r |= bit;
+            } else {
+                *r &= !bit;
+            }
+        }
+    }
+
+    pub fn family_keys(&self) -> &[u8; 9] {
+        &self.family_keys
+    }
+
+    pub fn press_mic(&mut self, pressed: bool) {
+        self.mic_key = pressed;
+    }
+
+    pub fn set_mic_input(&mut self, on: bool) {
+        self.mic_input = on;
+    }
+
+    pub fn mic(&self) -> bool {
+        self.mic_key || self.mic_input
+    }
+
+    pub fn press_mat(&mut self, position: usize, pressed: bool) {
+        if position < 12 {
+            if pressed {
+                self.mat |= 1 << position;
+            } else {
+                self.mat &= !(1 << position);
+            }
+        }
+    }
+
+    pub fn mat(&self) -> u16 {
+        self.mat
+    }
+
+    pub fn set_gamepad(&mut self, player: usize, buttons: u8) {
+        if player < PLAYERS {
+            self.gamepad[player] = buttons;
+        }
+    }
+
+    pub fn set_buttons(&mut self, player: usize, buttons: u8) {
+        if player < PLAYERS {
+            self.external[player] = buttons;
+        }
+    }
+
+    pub fn set_pointer(&mut self, pointer: Option<(u8, u8)>) {
+        self.pointer = pointer;
+    }
+
+    pub fn press_pointer(&mut self, pressed: bool) {
+        self.pressed = pressed;
+        if pressed {
+            self.clicked = true;
+        }
+    }
+
+    pub fn pointer(&self) -> Option<(u8, u8)> {
+        self.pointer
+    }
+
+    // Whether the pointer is pressed or clicked since the last frame
+    pub fn take_pressed(&mut self) -> bool {
+        let pressed = self.pressed || self.clicked;
+        self.clicked = false;
+        pressed
+    }
+
+    pub fn buttons(&self, player: usize) -> u8 {
+        self.keyboard[player] | self.gamepad[player] | self.external[player]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::controller::{BUTTON_A, BUTTON_B, BUTTON_START};
+
+    #[test]
+    fn test_keys() {
+        let mut input = Input::new();
+        input.press_key(0, BUTTON_A, true);
+        input.press_key(0, BUTTON_START, true);
+        input.press_key(1, BUTTON_B, true);
+        input.press_key(0, BUTTON_A, false);
+        assert_eq!(input.buttons(0), BUTTON_START);
+        assert_eq!(input.buttons(1), BUTTON_B);
+
+        input.press_mat(0, true);
+        input.press_mat(11, true);
+        input.press_mat(12, true);
+        assert_eq!(input.mat(), 0x801);
+        input.press_mat(0, false);
+        assert_eq!(input.mat(), 0x800);
+
+        input.release_keys();
+        assert_eq!(input.buttons(0), 0);
+        assert_eq!(input.buttons(1), 0);
+        assert_eq!(input.mat(), 0);
+    }
+
+    #[test]
+    fn test_combined() {
+        let mut input = Input::new();
+        input.set_buttons(0, BUTTON_A);
+        input.press_key(0, BUTTON_B, true);
+        input.set_gamepad(0, BUTTON_START);
+        assert_eq!(input.buttons(0), BUTTON_A | BUTTON_B | BUTTON_START);
+        input.release_keys();
+        assert_eq!(input.buttons(0), BUTTON_A | BUTTON_START);
+        // Unknown players are ignored
+        input.set_buttons(5, BUTTON_A);
+        input.press_key(5, BUTTON_A, true);
+        input.set_gamepad(5, BUTTON_A);
+    }
+
+    #[test]
+    fn test_mic() {
+        let mut input = Input::new();
+        assert!(!input.mic());
+        input.set_mic_input(true);
+        assert!(input.mic());
+        input.set_mic_input(false);
+        input.press_mic(true);
+        assert!(input.mic());
+        input.release_keys();
+        assert!(!input.mic());
+    }
+
+    #[test]
+    fn test_family_keys() {
+        let mut input = Input::new();
+        input.press_family_key(8, 0x40, true);
+        input.press_family_key(0, 0x01, true);
+        input.press_family_key(9, 0x01, true);
+        input.press_family_key(0, 0x01, false);
+        assert_eq!(input.family_keys(), &[0, 0, 0, 0, 0, 0, 0, 0, 0x40]);
+        input.release_keys();
+        assert_eq!(input.family_keys(), &[0; 9]);
+    }
+
+    #[test]
+    fn test_pointer() {
+        let mut input = Input::new();
+        input.set_pointer(Some((10, 20)));
+        assert_eq!(input.pointer(), Some((10, 20)));
+        assert!(!input.take_pressed());
+
+        // A click within a frame
+        input.press_pointer(true);
+        input.press_pointer(false);
+        assert!(input.take_pressed());
+        assert!(!input.take_pressed());
+
+        input.press_pointer(true);
+        assert!(input.take_pressed());
+        assert!(input.take_pressed());
+    }
+}
@@ -1,3 +1,6 @@
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
 const MEMORY_SIZE: usize = 2048;
 
 pub struct Memory {
@@ -36,6 +39,16 @@ impl Memory {
     }
 }
 
+impl Stateful for Memory {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.values);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        r.read_bytes(&mut self.values)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,85 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+use wasm_bindgen::prelude::{Closure, JsValue};
+use wasm_bindgen::JsCast;
+use web_sys::{AudioContext, AnalyserNode, MediaStream, MediaStreamConstraints, window};
+use js_sys::{Object, Reflect};
+
+// The microphone of the controller 2 is on when the input level reaches this
+const MIC_LEVEL: f32 = 0.1;
+const FFT_SIZE: u32 = 1024;
+
+// Reads the level of the browser microphone through Web Audio
+pub struct Microphone {
+    // Available after the user allows the microphone
+    analyser: Rc<RefCell<Option<AnalyserNode>>>,
+    samples: Vec<f32>
+}
+
+impl Microphone {
+    pub fn new() -> Self {
+        let analyser = Rc::new(RefCell::new(None));
+        // The emulator works without the microphone if it's denied
+        let _ = request_microphone(analyser.clone());
+        Self {
+            analyser,
+            samples: vec![0.0; FFT_SIZE as usize]
+        }
+    }
+
+    pub fn blown(&mut self) -> bool {
+        match self.analyser.borrow().as_ref() {
+            Some(analyser) => {
+                analyser.get_float_time_domain_data(&mut self.samples);
+                level(&self.samples) >= MIC_LEVEL
+            },
+            None => false
+        }
+    }
+}
+
+fn request_microphone(analyser: Rc<RefCell<Option<AnalyserNode>>>) -> Result<(), JsValue> {
+    let constraints = Object::new();
+    Reflect::set(&constraints, &JsValue::from_str("audio"), &JsValue::TRUE)?;
+    let promise = window().unwrap().navigator().media_devices()?
+        .get_user_media_with_constraints(constraints.unchecked_ref::<MediaStreamConstraints>())?;
+    let callback = Closure::wrap(Box::new(move |stream: JsValue| {
+        let _ = connect_analyser(&analyser, stream);
+    }) as Box<dyn FnMut(JsValue)>);
+    let _ = promise.then(&callback);
+    callback.forget();
+    Ok(())
+}
+
+fn connect_analyser(analyser: &Rc<RefCell<Option<AnalyserNode>>>, stream: JsValue) -> Result<(), JsValue> {
+    let stream = stream.dyn_into::<MediaStream>()?;
+    let ctx = AudioContext::new()?;
+    let source = ctx.create_media_stream_source(&stream)?;
+    let node = ctx.create_analyser()?;
+    node.set_fft_size(FFT_SIZE);
+    source.connect_with_audio_node(&node)?;
+    *analyser.borrow_mut() = Some(node);
+    Ok(())
+}
+
+// The root mean square of the samples
+fn level(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    let sum: f32 = samples.iter().map(|s| s * s).sum();
+    (sum / samples.len() as f32).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level() {
+        assert_eq!(level(&[]), 0.0);
+        assert_eq!(level(&[0.0; 16]), 0.0);
+        assert!((level(&[0.5, -0.5, 0.5, -0.5]) - 0.5).abs() < 1e-6);
+        assert!(level(&[0.05, -0.05]) < MIC_LEVEL);
+    }
+}
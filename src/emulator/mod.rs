@@ -1,5 +1,12 @@
 pub use self::emulator::Emulator;
 pub use self::emulator::new_emulator;
+pub use self::input::{Input, PLAYERS};
+pub use self::pacer::Pacing;
+pub use self::controller::{
+    Device,
+    BUTTON_A, BUTTON_B, BUTTON_SELECT, BUTTON_START,
+    BUTTON_UP, BUTTON_DOWN, BUTTON_LEFT, BUTTON_RIGHT
+};
 
 mod emulator;
 mod cpu;
@@ -8,4 +15,9 @@ mod apu;
 mod memory;
 mod bus;
 mod instruction;
-mod controller;
\ No newline at end of file
+mod controller;
+mod input;
+mod gamepad;
+mod microphone;
+mod audio;
+mod pacer;
\ No newline at end of file
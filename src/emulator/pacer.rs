@@ -0,0 +1,173 @@
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use wasm_bindgen::prelude::Closure;
+use wasm_bindgen::JsCast;
+use web_sys::window;
+use js_sys::Function;
+use super::audio::{Audio, TARGET_BUFFERED};
+
+// The frames run in an animation frame at most, so the game doesn't rush after the page is hidden
+const MAX_FRAMES: u32 = 4;
+
+// How the frames are scheduled
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Pacing {
+    // window.setInterval with the period of a frame
+    Interval,
+    // requestAnimationFrame, running the frames of the elapsed time
+    AnimationFrame,
+    // requestAnimationFrame, running the frames until the audio buffer is filled.
+    // It falls back to AnimationFrame if no audio is played.
+    Audio
+}
+
+type AnimationCallback = Rc<RefCell<Option<Closure<dyn FnMut(f64)>>>>;
+
+// Runs the frames in the pacing until it's dropped
+pub struct Pacer {
+    interval: Option<(i32, Closure<dyn FnMut()>)>,
+    // The handle of the last requested animation frame
+    animation: Option<(Rc<Cell<i32>>, AnimationCallback)>
+}
+
+impl Pacer {
+    pub fn start(pacing: Pacing, fps: f64, frame: Box<dyn FnMut()>,
+                 audio: Option<Rc<RefCell<Audio>>>) -> Self {
+        match pacing {
+            Pacing::Interval => {
+                let closure = Closure::wrap(frame);
+                let handle = window().unwrap()
+                    .set_interval_with_callback_and_timeout_and_arguments_0(
+                        closure.as_ref().unchecked_ref::<Function>(),
+                        (1000f64 / fps).floor() as i32
+                    ).unwrap();
+                Self {
+                    interval: Some((handle, closure)),
+                    animation: None
+                }
+            },
+            _ => {
+                let audio = if pacing == Pacing::Audio { audio } else { None };
+                Self {
+                    interval: None,
+                    animation: Some(start_animation(fps, frame, audio))
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Pacer {
+    fn drop(&mut self) {
+        if let Some((handle, _)) = self.interval.take() {
+            window().unwrap().clear_interval_with_handle(handle);
+        }
+        if let Some((handle, callback)) = self.animation.take() {
+            let _ = window().unwrap().cancel_animation_frame(handle.get());
+            // Breaks the reference from the callback to itself
+            callback.borrow_mut().take();
+        }
+    }
+}
+
+fn start_animation(fps: f64, mut frame: Box<dyn FnMut()>,
+                   audio: Option<Rc<RefCell<Audio>>>) -> (Rc<Cell<i32>>, AnimationCallback) {
+    let handle = Rc::new(Cell::new(0));
+    let callback: AnimationCallback = Rc::new(RefCell::new(None));
+    let mut accumulator = Accumulator::new(fps);
+    let (h, c) = (handle.clone(), callback.clone());
+    *callback.borrow_mut() = Some(Closure::wrap(Box::new(move |now: f64| {
+        let frames = accumulator.advance(now);
+        match audio.as_ref() {
+            Some(a) if a.borrow().ready() => {
+                for _ in 0..MAX_FRAMES {
+                    if a.borrow().buffered() >= TARGET_BUFFERED {
+                        break;
+                    }
+                    frame();
+                }
+            },
+            _ => for _ in 0..frames {
+                frame();
+            }
+        }
+        if let Some(cb) = c.borrow().as_ref() {
+            h.set(request_animation(cb));
+        }
+    }) as Box<dyn FnMut(f64)>));
+    handle.set(request_animation(callback.borrow().as_ref().unwrap()));
+    (handle, callback)
+}
+
+fn request_animation(callback: &Closure<dyn FnMut(f64)>) -> i32 {
+    window().unwrap()
+        .request_animation_frame(callback.as_ref().unchecked_ref::<Function>())
+        .unwrap()
+}
+
+// Accumulates the time between the animation frames into the frames to run
+struct Accumulator {
+    // The time of a frame in ms
+    frame_time: f64,
+    time: f64,
+    last: Option<f64>
+}
+
+impl Accumulator {
+    fn new(fps: f64) -> Self {
+        Self {
+            frame_time: 1000.0 / fps,
+            time: 0.0,
+            last: None
+        }
+    }
+
+    // Returns the number of the frames to run at the timestamp in ms
+    fn advance(&mut self, now: f64) -> u32 {
+        if let Some(last) = self.last {
+            self.time += now - last;
+        }
+        self.last = Some(now);
+        let frames = (self.time / self.frame_time) as u32;
+        if frames > MAX_FRAMES {
+            self.time = 0.0;
+            MAX_FRAMES
+        } else {
+            self.time -= frames as f64 * self.frame_time;
+            frames
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_rate() {
+        let mut acc = Accumulator::new(50.0);
+        assert_eq!(acc.advance(1000.0), 0);
+        let frames: Vec<u32> = (1..=50).map(|i| acc.advance(1000.0 + i as f64 * 20.0)).collect();
+        assert!(frames.iter().all(|f| *f == 1));
+    }
+
+    #[test]
+    fn test_faster_display() {
+        // A display of 125Hz runs 50 frames per second
+        let mut acc = Accumulator::new(50.0);
+        acc.advance(0.0);
+        let frames: Vec<u32> = (1..=125).map(|i| acc.advance(i as f64 * 8.0)).collect();
+        assert!(frames.iter().all(|f| *f <= 1));
+        assert_eq!(frames.iter().sum::<u32>(), 50);
+    }
+
+    #[test]
+    fn test_pause() {
+        let mut acc = Accumulator::new(60.0);
+        acc.advance(0.0);
+        // The time after a long pause is dropped
+        assert_eq!(acc.advance(10000.0), MAX_FRAMES);
+        assert_eq!(acc.advance(10010.0), 0);
+        assert_eq!(acc.advance(10017.0), 1);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::rom::Rom;
+use crate::rom::{Rom, Mirroring as Mode};
 
 pub struct Mirroring {
     read: fn(u16, memory: &[u8], _rom: &Rom) -> u8,
@@ -17,23 +17,49 @@ impl Mirroring {
 
 pub fn select_mirroring(rom: &Rom) -> Mirroring {
     match rom.mirroring() {
-        Some(m) => {
-            if m == 0 {
-                Mirroring {
-                    read: horizontal_mirroring_read,
-                    write: horizontal_mirroring_write
-                }
-            } else {
-                Mirroring {
-                    read: vertical_mirroring_read,
-                    write: vertical_mirroring_write
-                }
-            }
+        Mode::SingleScreenLower => Mirroring {
+            read: single_screen_lower_read,
+            write: single_screen_lower_write
+        },
+        Mode::SingleScreenUpper => Mirroring {
+            read: single_screen_upper_read,
+            write: single_screen_upper_write
+        },
+        Mode::Horizontal => Mirroring {
+            read: horizontal_mirroring_read,
+            write: horizontal_mirroring_write
         },
-        None => panic!("Mirroring not supported.")
+        Mode::Vertical => Mirroring {
+            read: vertical_mirroring_read,
+            write: vertical_mirroring_write
+        },
+        Mode::FourScreen => Mirroring {
+            read: four_screen_read,
+            write: four_screen_write
+        },
+        Mode::Mapped => Mirroring {
+            read: mapped_read,
+            write: mapped_write
+        }
     }
 }
 
+fn single_screen_lower_read(addr: u16, memory: &[u8], _rom: &Rom) -> u8 {
+    memory[(addr & 0x03FF) as usize]
+}
+
+fn single_screen_lower_write(addr: u16, v: u8, memory: &mut [u8], _rom: &mut Rom) {
+    memory[(addr & 0x03FF) as usize] = v;
+}
+
+fn single_screen_upper_read(addr: u16, memory: &[u8], _rom: &Rom) -> u8 {
+    memory[(0x0400 | (addr & 0x03FF)) as usize]
+}
+
+fn single_screen_upper_write(addr: u16, v: u8, memory: &mut [u8], _rom: &mut Rom) {
+    memory[(0x0400 | (addr & 0x03FF)) as usize] = v;
+}
+
 fn horizontal_mirroring_read(addr: u16, memory: &[u8], _rom: &Rom) -> u8 {
     let index = addr & 0x0BFF;
     if index >= 0x0800 {
@@ -60,6 +86,22 @@ fn vertical_mirroring_write(addr: u16, v: u8, memory: &mut [u8], _rom: &mut Rom)
     memory[(addr & 0x07FF) as usize] = v;
 }
 
+fn four_screen_read(addr: u16, memory: &[u8], _rom: &Rom) -> u8 {
+    memory[(addr & 0x0FFF) as usize]
+}
+
+fn four_screen_write(addr: u16, v: u8, memory: &mut [u8], _rom: &mut Rom) {
+    memory[(addr & 0x0FFF) as usize] = v;
+}
+
+fn mapped_read(addr: u16, memory: &[u8], rom: &Rom) -> u8 {
+    rom.mapper().read_nametable(addr, &memory[..0x0800])
+}
+
+fn mapped_write(addr: u16, v: u8, memory: &mut [u8], rom: &mut Rom) {
+    rom.mapper_mut().write_nametable(addr, v, &mut memory[..0x0800]);
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -112,4 +154,38 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_single_screen_read_write() {
+        let mut rom = crate::rom::tests::mock();
+        let mut memory: [u8; 2048] = [0; 2048];
+
+        for i in 0..0x0400 as u16 {
+            let v = rand::random::<u8>();
+            single_screen_lower_write(i, v, &mut memory, &mut rom);
+            for n in 0..4 {
+                assert_eq!(single_screen_lower_read(0x0400 * n + i, &memory, &rom), v);
+            }
+
+            let v = rand::random::<u8>();
+            single_screen_upper_write(0x0800 + i, v, &mut memory, &mut rom);
+            for n in 0..4 {
+                assert_eq!(single_screen_upper_read(0x0400 * n + i, &memory, &rom), v);
+            }
+            assert_eq!(memory[0x0400 + i as usize], v);
+        }
+    }
+
+    #[test]
+    fn test_four_screen_read_write() {
+        let mut rom = crate::rom::tests::mock();
+        let mut memory: [u8; 4096] = [0; 4096];
+
+        for i in 0..0x1000 as u16 {
+            let v = rand::random::<u8>();
+            four_screen_write(i, v, &mut memory, &mut rom);
+            assert_eq!(four_screen_read(i, &memory, &rom), v);
+            assert_eq!(memory[i as usize], v);
+        }
+    }
+
 }
\ No newline at end of file
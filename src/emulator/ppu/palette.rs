@@ -1,3 +1,6 @@
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
 const SIZE: usize = 32;
 
 pub struct Palette {
@@ -32,6 +35,16 @@ impl Palette {
     }
 }
 
+impl Stateful for Palette {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.data);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        r.read_bytes(&mut self.data)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1,10 +1,12 @@
 use web_sys::CanvasRenderingContext2d;
 use super::renderer::Renderer;
-use crate::rom::{Rom, Timing};
-use crate::emulator::ppu::mirroring::{Mirroring, select_mirroring};
+use crate::rom::{Rom, Timing, PpuFetch};
+use crate::emulator::ppu::mirroring::select_mirroring;
 use crate::emulator::ppu::registers::{PPUControl, PPUMask, PPUStatus, PPUScroll, PPUAddress};
 use crate::emulator::ppu::palette::Palette;
 use crate::init::{RAW_WIDTH, RAW_HEIGHT};
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
 
 type RegisterWriter = fn(&mut PPU, u8, &mut Rom) -> bool;
 
@@ -15,13 +17,21 @@ const PHRASE_POST_RENDER: Phrase = 2;
 const PHRASE_START_VBL: Phrase = 3;
 
 const SCANLINE_CLK: u32 = 341;
+
+// The photodiode of the light gun keeps sensing for about this number of scanlines
+// after the beam passes the target
+const LIGHT_LINES: u16 = 26;
+// The pixels within this distance of the target are seen by the light gun
+const LIGHT_RADIUS: u8 = 2;
+const LIGHT_BRIGHTNESS: u8 = 0x80;
 const K: usize = 1024;
 
 
 pub struct PPU {
     renderer: Renderer,
 
-    memory: [u8; 2 * K],
+    // 2K VRAM of the console and 2K extra VRAM for four-screen mirroring
+    memory: [u8; 4 * K],
     data_buffer: u8,
     palette: Palette,
 
@@ -45,9 +55,7 @@ pub struct PPU {
 
     phrase: Phrase,
     phrase_clk: u32,
-    clk_counter: u32,
-
-    mirroring: Mirroring
+    clk_counter: u32
 }
 
 impl PPU {
@@ -62,7 +70,7 @@ impl PPU {
             oam_addr: 0,
             oam_index: 0,
             palette: Palette::new(),
-            memory: [0; 2 * K],
+            memory: [0; 4 * K],
             data_buffer: 0,
             wait_cpu: true,
             even: true,
@@ -74,28 +82,35 @@ impl PPU {
             register_writers: make_register_writers(),
             phrase: PHRASE_PRE_RENDER,
             phrase_clk: SCANLINE_CLK,
-            clk_counter: 0,
-            mirroring: select_mirroring(&rom)
+            clk_counter: 0
         }
     }
 
     // Return (end-of-frame, nmi)
-    pub fn ticks(&mut self, t: u8, rom: &Rom) -> (bool, bool) {
+    pub fn ticks(&mut self, t: u8, rom: &mut Rom) -> (bool, bool) {
         let mut end_frame = false;
         let mut nmi = false;
         for _tick in 0..t {
+            rom.mapper_mut().notify_ppu_tick();
             match self.phrase {
                 PHRASE_PRE_RENDER => {
                     if self.clk_counter == 1 {
                         self.ppu_status.set_vertical_blank(false);
                         self.ppu_status.set_sprite_overflow(false);
                         self.ppu_status.set_sprite_0_hit(false);
+                        if self.rendering() {
+                            rom.mapper_mut().notify_ppu_address(self.ppu_ctrl.background_pattern());
+                        }
+                    } else if self.clk_counter == 257 && self.rendering() {
+                        rom.mapper_mut().notify_ppu_address(self.dummy_sprite_pattern());
                     }
                 },
                 PHRASE_VISIBLE_RENDER => {
                     let tick = (self.clk_counter % SCANLINE_CLK) as u16;
                     if tick == 0 {
                         self.renderer.clear_buffer();
+                        let line = (self.clk_counter / SCANLINE_CLK) as u8;
+                        rom.mapper_mut().notify_scanline(if self.rendering() { Some(line) } else { None });
                     } else {
                         let line = (self.clk_counter / SCANLINE_CLK) as u16;
 
@@ -115,6 +130,11 @@ impl PPU {
                             let nt_offset_x = x / 8;
                             let nt_offset_y = y / 8;
 
+                            rom.mapper_mut().notify_fetch(PpuFetch::Background((tick - 1) as u8));
+                            let pattern_index = self.read(
+                                nt_base + nt_offset_y * 32 + nt_offset_x,
+                                rom);
+
                             let attr = self.read(
                                 nt_base + 960 + nt_offset_y / 4 * 8 + nt_offset_x / 4,
                                 rom);
@@ -122,9 +142,6 @@ impl PPU {
                             let palette_index= (attr >> attr_offset) & 0x03;
                             let palette_addr = 0x3F00 + 4 * palette_index as u16;
 
-                            let pattern_index = self.read(
-                                nt_base + nt_offset_y * 32 + nt_offset_x,
-                                rom);
                             let pattern_addr = self.ppu_ctrl.background_pattern()
                                 + pattern_index as u16 * 16 + y % 8;
                             let pattern_low = self.read(pattern_addr, rom);
@@ -140,6 +157,13 @@ impl PPU {
                             }
                         }
 
+                        // Sprite patterns are fetched even if there's no sprite on the line,
+                        // which clocks the scanline counter of some mappers
+                        if tick == 257 && self.rendering() {
+                            rom.mapper_mut().notify_fetch(PpuFetch::Sprite);
+                            rom.mapper_mut().notify_ppu_address(self.dummy_sprite_pattern());
+                        }
+
                         if self.ppu_mask.show_sprite() && line > 0 {
                             if tick == 1 {
                                 self.oam_clear = true;
@@ -198,7 +222,11 @@ impl PPU {
                         }
                     }
                 },
-                PHRASE_POST_RENDER => {},
+                PHRASE_POST_RENDER => {
+                    if self.clk_counter == 0 {
+                        rom.mapper_mut().notify_scanline(None);
+                    }
+                },
                 PHRASE_START_VBL => {
                     if self.clk_counter == 1 {
                         self.ppu_status.set_vertical_blank(true);
@@ -236,6 +264,33 @@ impl PPU {
         (end_frame, nmi)
     }
 
+    // Whether the light gun aiming at the pixel senses the light at the moment,
+    // which requires the beam just passed the pixel and it's bright enough.
+    pub fn sense_light(&self, x: u8, y: u8) -> bool {
+        let beam = match self.phrase {
+            PHRASE_VISIBLE_RENDER => (self.clk_counter / SCANLINE_CLK) as u16,
+            PHRASE_POST_RENDER => RAW_HEIGHT as u16,
+            PHRASE_START_VBL => RAW_HEIGHT as u16 + 1 + (self.clk_counter / SCANLINE_CLK) as u16,
+            _ => return false
+        };
+        let y16 = y as u16;
+        beam > y16 && beam - y16 <= LIGHT_LINES
+            && self.renderer.brightness(x, y, LIGHT_RADIUS) >= LIGHT_BRIGHTNESS
+    }
+
+    fn rendering(&self) -> bool {
+        self.ppu_mask.show_background() || self.ppu_mask.show_sprite()
+    }
+
+    // The pattern address of tile $FF, which is fetched for the empty sprite slots
+    fn dummy_sprite_pattern(&self) -> u16 {
+        if self.ppu_ctrl.large_sprite() {
+            0x1FF0
+        } else {
+            self.ppu_ctrl.sprite_pattern() | 0x0FF0
+        }
+    }
+
     fn detect_sprite(&mut self, line: u8) {
         self.sprite_count = 0;
         self.sprite_0 = false;
@@ -268,7 +323,7 @@ impl PPU {
         }
     }
 
-    pub fn read_register(&mut self, addr: u16, rom: &Rom) -> u8 {
+    pub fn read_register(&mut self, addr: u16, rom: &mut Rom) -> u8 {
         let index = addr & 0x07;
         if index == 2 {
             // PPU Status
@@ -300,25 +355,27 @@ impl PPU {
         (self.register_writers[index as usize])(self, v, rom)
     }
 
-    fn read(&self, addr: u16, rom: &Rom) -> u8 {
+    fn read(&self, addr: u16, rom: &mut Rom) -> u8 {
+        rom.mapper_mut().notify_ppu_address(addr);
         let mark = addr & 0xF000;
         if mark == 0 || mark == 0x1000 {
             rom.mapper().read_chr(addr)
         } else if addr < 0x3EFF {
             let address = addr & 0x0FFF;
-            (self.mirroring.read())(address, &self.memory, &rom)
+            (select_mirroring(rom).read())(address, &self.memory, rom)
         } else {
             self.palette.read(addr, self.ppu_mask.grey_scale())
         }
     }
 
     fn write(&mut self, addr: u16, v: u8, rom: &mut Rom) {
+        rom.mapper_mut().notify_ppu_address(addr);
         let mark = addr & 0xF000;
         if mark == 0 || mark == 0x1000 {
             rom.mapper_mut().write_chr(addr, v);
         } else if addr < 0x3EFF {
             let address = addr & 0x0FFF;
-            (self.mirroring.write())(address, v, &mut self.memory, rom);
+            (select_mirroring(rom).write())(address, v, &mut self.memory, rom);
         } else {
             self.palette.write(addr, v);
         }
@@ -340,6 +397,76 @@ impl PPU {
 
 }
 
+impl Stateful for PPU {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.memory);
+        w.write_u8(self.data_buffer);
+        self.palette.save_state(w);
+
+        w.write_bytes(&self.oam);
+        w.write_bytes(&self.secondary_oam);
+        w.write_u8(self.sprite_count);
+        w.write_bool(self.sprite_0);
+        w.write_bool(self.oam_clear);
+        w.write_u8(self.oam_addr);
+        w.write_u8(self.oam_index);
+
+        w.write_bool(self.wait_cpu);
+        w.write_bool(self.even);
+
+        self.ppu_ctrl.save_state(w);
+        self.ppu_mask.save_state(w);
+        self.ppu_status.save_state(w);
+        self.ppu_scroll.save_state(w);
+        self.ppu_addr.save_state(w);
+
+        w.write_u8(self.phrase);
+        w.write_u32(self.phrase_clk);
+        w.write_u32(self.clk_counter);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        r.read_bytes(&mut self.memory)?;
+        self.data_buffer = r.read_u8()?;
+        self.palette.load_state(r)?;
+
+        r.read_bytes(&mut self.oam)?;
+        r.read_bytes(&mut self.secondary_oam)?;
+        self.sprite_count = r.read_u8()?;
+        self.sprite_0 = r.read_bool()?;
+        self.oam_clear = r.read_bool()?;
+        self.oam_addr = r.read_u8()?;
+        self.oam_index = r.read_u8()?;
+
+        self.wait_cpu = r.read_bool()?;
+        self.even = r.read_bool()?;
+
+        self.ppu_ctrl.load_state(r)?;
+        self.ppu_mask.load_state(r)?;
+        self.ppu_status.load_state(r)?;
+        self.ppu_scroll.load_state(r)?;
+        self.ppu_addr.load_state(r)?;
+
+        self.phrase = r.read_u8()?;
+        if self.phrase > PHRASE_START_VBL {
+            return Err(Message::MalformedState);
+        }
+        self.phrase_clk = r.read_u32()?;
+        self.clk_counter = r.read_u32()?;
+        // The counter is compared by equality in ticks, so it can't start beyond the phrase
+        let phrase_clk_valid = match self.phrase {
+            PHRASE_PRE_RENDER => self.phrase_clk == SCANLINE_CLK || self.phrase_clk == SCANLINE_CLK - 1,
+            PHRASE_VISIBLE_RENDER => self.phrase_clk == 240 * SCANLINE_CLK,
+            PHRASE_POST_RENDER => self.phrase_clk == SCANLINE_CLK,
+            _ => self.phrase_clk == 20 * SCANLINE_CLK
+        };
+        if !phrase_clk_valid || self.clk_counter >= self.phrase_clk {
+            return Err(Message::MalformedState);
+        }
+        Ok(())
+    }
+}
+
 fn make_register_writers() -> [RegisterWriter; 8] {
     [
         // PPU_CTRL
@@ -389,9 +516,10 @@ fn make_register_writers() -> [RegisterWriter; 8] {
             false
         },
         // PPU_ADDR
-        |ppu, value, _| -> bool {
+        |ppu, value, rom| -> bool {
             if !ppu.wait_cpu {
                 ppu.ppu_addr.write(value);
+                rom.mapper_mut().notify_ppu_address(ppu.ppu_addr.addr());
             }
             false
         },
@@ -408,12 +536,39 @@ fn make_register_writers() -> [RegisterWriter; 8] {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_sense_light() {
+        let rom = crate::rom::tests::mock();
+        let mut ppu = PPU::new(None, &rom);
+        for i in 0..RAW_WIDTH {
+            ppu.renderer.set_background(i as u8, 0x30);
+        }
+        for line in 40..56 {
+            ppu.renderer.merge_line(line, 0x0F, &ppu.ppu_mask);
+        }
+
+        ppu.phrase = PHRASE_VISIBLE_RENDER;
+        ppu.clk_counter = 60 * SCANLINE_CLK;
+        assert!(ppu.sense_light(100, 50));
+        // The dark area, and the line the beam hasn't reached
+        assert!(!ppu.sense_light(100, 10));
+        assert!(!ppu.sense_light(100, 70));
+        ppu.clk_counter = 45 * SCANLINE_CLK;
+        assert!(!ppu.sense_light(100, 50));
+        // The light fades long after the beam passes
+        ppu.phrase = PHRASE_START_VBL;
+        ppu.clk_counter = 0;
+        assert!(!ppu.sense_light(100, 50));
+        ppu.phrase = PHRASE_PRE_RENDER;
+        assert!(!ppu.sense_light(100, 50));
+    }
+
     #[test]
     #[should_panic]
     fn test_ctrl_read() {
-        let rom = crate::rom::tests::mock();
+        let mut rom = crate::rom::tests::mock();
         let mut ppu = PPU::new(None, &rom);
-        ppu.read_register(0x2000, &rom);
+        ppu.read_register(0x2000, &mut rom);
     }
 
     #[test]
@@ -445,9 +600,9 @@ mod tests {
     #[test]
     #[should_panic]
     fn test_mask_read() {
-        let rom = crate::rom::tests::mock();
+        let mut rom = crate::rom::tests::mock();
         let mut ppu = PPU::new(None, &rom);
-        ppu.read_register(0x2101, &rom);
+        ppu.read_register(0x2101, &mut rom);
     }
 
     #[test]
@@ -469,9 +624,9 @@ mod tests {
 
     #[test]
     fn test_status_read() {
-        let rom = crate::rom::tests::mock();
+        let mut rom = crate::rom::tests::mock();
         let mut ppu = PPU::new(None, &rom);
-        let status = ppu.read_register(0x2002, &rom);
+        let status = ppu.read_register(0x2002, &mut rom);
         assert_eq!(status, 0);
     }
 
@@ -486,9 +641,9 @@ mod tests {
     #[test]
     #[should_panic]
     fn test_scroll_read() {
-        let rom = crate::rom::tests::mock();
+        let mut rom = crate::rom::tests::mock();
         let mut ppu = PPU::new(None, &rom);
-        ppu.read_register(0x2005, &rom);
+        ppu.read_register(0x2005, &mut rom);
     }
 
     #[test]
@@ -511,4 +666,35 @@ mod tests {
         assert_eq!(ppu.ppu_scroll.x(), 3);
         assert_eq!(ppu.ppu_scroll.y(), 4);
     }
+
+    #[test]
+    fn test_state() {
+        let rom = crate::rom::tests::mock();
+        let mut ppu = PPU::new(None, &rom);
+        ppu.phrase = PHRASE_VISIBLE_RENDER;
+        ppu.phrase_clk = 240 * SCANLINE_CLK;
+        ppu.clk_counter = 100 * SCANLINE_CLK;
+        let mut w = StateWriter::new();
+        ppu.save_state(&mut w);
+        let data = w.finish();
+
+        let mut other = PPU::new(None, &rom);
+        let mut r = StateReader::new(&data).ok().unwrap();
+        assert!(other.load_state(&mut r).is_ok());
+        assert!(r.finished());
+        assert_eq!(other.clk_counter, 100 * SCANLINE_CLK);
+
+        // The counter past the end of the phrase
+        let mut bad = data.to_vec();
+        let len = bad.len();
+        bad[len - 4..].copy_from_slice(&(240 * SCANLINE_CLK).to_le_bytes());
+        let mut r = StateReader::new(&bad).ok().unwrap();
+        assert!(other.load_state(&mut r).is_err());
+
+        // The length of another phrase
+        let mut bad = data.to_vec();
+        bad[len - 8..len - 4].copy_from_slice(&(20 * SCANLINE_CLK).to_le_bytes());
+        let mut r = StateReader::new(&bad).ok().unwrap();
+        assert!(other.load_state(&mut r).is_err());
+    }
 }
\ No newline at end of file
@@ -1,3 +1,6 @@
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
 // PPU_CTRL
 pub struct PPUControl {
     nt_base: u16,
@@ -39,7 +42,6 @@ impl PPUControl {
         self.vram_step
     }
 
-    #[allow(dead_code)]
     pub fn large_sprite(&self) -> bool {
         self.large_sprite
     }
@@ -57,6 +59,27 @@ impl PPUControl {
     }
 }
 
+impl Stateful for PPUControl {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u16(self.nt_base);
+        w.write_u16(self.vram_step);
+        w.write_bool(self.large_sprite);
+        w.write_u16(self.sprite_pattern);
+        w.write_u16(self.background_pattern);
+        w.write_bool(self.nmi);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.nt_base = r.read_u16()?;
+        self.vram_step = r.read_u16()?;
+        self.large_sprite = r.read_bool()?;
+        self.sprite_pattern = r.read_u16()?;
+        self.background_pattern = r.read_u16()?;
+        self.nmi = r.read_bool()?;
+        Ok(())
+    }
+}
+
 // PPU_Mask
 pub struct PPUMask {
     grey_scale: bool,
@@ -134,6 +157,28 @@ impl PPUMask {
     }
 }
 
+impl Stateful for PPUMask {
+    fn save_state(&self, w: &mut StateWriter) {
+        for v in [self.grey_scale, self.show_background_left, self.show_sprite_left,
+                  self.show_background, self.show_sprite,
+                  self.emphasize_red, self.emphasize_green, self.emphasize_blue] {
+            w.write_bool(v);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.grey_scale = r.read_bool()?;
+        self.show_background_left = r.read_bool()?;
+        self.show_sprite_left = r.read_bool()?;
+        self.show_background = r.read_bool()?;
+        self.show_sprite = r.read_bool()?;
+        self.emphasize_red = r.read_bool()?;
+        self.emphasize_green = r.read_bool()?;
+        self.emphasize_blue = r.read_bool()?;
+        Ok(())
+    }
+}
+
 // PPU_Status
 pub struct PPUStatus {
     value: u8,
@@ -189,6 +234,21 @@ impl PPUStatus {
     }
 }
 
+impl Stateful for PPUStatus {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.value);
+        w.write_bool(self.vertical_blank);
+        w.write_bool(self.sprite_0_hit);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.value = r.read_u8()?;
+        self.vertical_blank = r.read_bool()?;
+        self.sprite_0_hit = r.read_bool()?;
+        Ok(())
+    }
+}
+
 // PPU_Scroll
 pub struct PPUScroll {
     x: u8,
@@ -223,6 +283,21 @@ impl PPUScroll {
     }
 }
 
+impl Stateful for PPUScroll {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u8(self.x);
+        w.write_u8(self.y);
+        w.write_bool(self.to_x);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.x = r.read_u8()?;
+        self.y = r.read_u8()?;
+        self.to_x = r.read_bool()?;
+        Ok(())
+    }
+}
+
 // PPU_Address
 pub struct PPUAddress {
     addr: u16,
@@ -264,4 +339,17 @@ impl PPUAddress {
         }
         self.high = !self.high;
     }
+}
+
+impl Stateful for PPUAddress {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u16(self.addr);
+        w.write_bool(self.high);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.addr = r.read_u16()?;
+        self.high = r.read_bool()?;
+        Ok(())
+    }
 }
\ No newline at end of file
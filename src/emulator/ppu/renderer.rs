@@ -104,6 +104,22 @@ impl Renderer {
         }
     }
 
+    // The average luminance of the pixels around the point
+    pub fn brightness(&self, x: u8, y: u8, radius: u8) -> u8 {
+        let (x, y, radius) = (x as usize, y as usize, radius as usize);
+        let mut sum = 0u32;
+        let mut count = 0u32;
+        for j in y.saturating_sub(radius)..(y + radius + 1).min(RAW_HEIGHT) {
+            for i in x.saturating_sub(radius)..(x + radius + 1).min(RAW_WIDTH) {
+                let index = CHANNELS * RAW_WIDTH * j + CHANNELS * i;
+                sum += (self.data[index] as u32 * 299 + self.data[index + 1] as u32 * 587
+                    + self.data[index + 2] as u32 * 114) / 1000;
+                count += 1;
+            }
+        }
+        (sum / count) as u8
+    }
+
     pub fn render(&self) {
         match self.ctx.as_ref() {
             Some(c) => {
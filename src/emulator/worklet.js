@@ -0,0 +1,52 @@
+// Plays the samples posted by the emulator, which are buffered in a ring.
+// The oldest samples are dropped when the ring is full, and silence is played when it's empty.
+const CAPACITY = 4096;
+// The number of the buffered samples is reported to the emulator every this number of blocks
+const REPORT_BLOCKS = 4;
+
+class PixelHeroProcessor extends AudioWorkletProcessor {
+    constructor() {
+        super();
+        this.ring = new Float32Array(CAPACITY);
+        this.head = 0;
+        this.size = 0;
+        this.blocks = 0;
+        this.port.onmessage = e => this.push(e.data);
+    }
+
+    push(samples) {
+        for (let i = 0; i < samples.length; i++) {
+            this.ring[(this.head + this.size) % CAPACITY] = samples[i];
+            if (this.size < CAPACITY) {
+                this.size++;
+            } else {
+                this.head = (this.head + 1) % CAPACITY;
+            }
+        }
+    }
+
+    process(inputs, outputs) {
+        const channels = outputs[0];
+        const first = channels[0];
+        for (let i = 0; i < first.length; i++) {
+            if (this.size > 0) {
+                first[i] = this.ring[this.head];
+                this.head = (this.head + 1) % CAPACITY;
+                this.size--;
+            } else {
+                first[i] = 0;
+            }
+        }
+        for (let c = 1; c < channels.length; c++) {
+            channels[c].set(first);
+        }
+        this.blocks++;
+        if (this.blocks === REPORT_BLOCKS) {
+            this.blocks = 0;
+            this.port.postMessage(this.size);
+        }
+        return true;
+    }
+}
+
+registerProcessor("pixel-hero-audio", PixelHeroProcessor);
@@ -4,6 +4,11 @@ pub enum Message {
     CanvasContextError,
     CartridgeAlreadyInserted,
     MalformedFileFormat,
+    UnsupportedMapper(u16),
+    NoCartridge,
+    MalformedState,
+    UnsupportedStateVersion(u16),
+    StateMismatch,
 }
 
 impl Message {
@@ -1,14 +1,20 @@
 use wasm_bindgen::prelude::{JsValue, wasm_bindgen, Closure};
 use wasm_bindgen::JsCast;
-use web_sys::{HtmlElement, HtmlCanvasElement, HtmlDivElement, Document, CanvasRenderingContext2d};
+use web_sys::{HtmlElement, HtmlCanvasElement, HtmlDivElement, Document, CanvasRenderingContext2d,
+              KeyboardEvent, MouseEvent, TouchEvent};
 use js_sys::Function;
-use crate::conf::Configuration;
+use crate::conf::{Configuration, KeyMap};
 use crate::i18n::Message;
-use crate::emulator::{Emulator, new_emulator};
+use crate::emulator::{Emulator, Input, Device, new_emulator};
+use std::rc::Rc;
+use std::cell::RefCell;
 
 pub const RAW_WIDTH: usize = 256;
 pub const RAW_HEIGHT: usize = 240;
 
+type MouseHandler = fn(&mut Input, Option<(u8, u8)>, &MouseEvent);
+type TouchHandler = fn(&mut Input, Option<(u8, u8)>);
+
 #[wasm_bindgen]
 pub fn create_emulator(element: &HtmlElement, o: &JsValue) -> Emulator {
     console_error_panic_hook::set_once();
@@ -18,7 +24,129 @@ pub fn create_emulator(element: &HtmlElement, o: &JsValue) -> Emulator {
 
     let conf = Configuration::new(o);
     let ctx = init_canvas(element, &conf).unwrap();
-    new_emulator(ctx, conf)
+    let input = Rc::new(RefCell::new(Input::new()));
+    init_keyboard(&input, conf.keys()).unwrap();
+    init_pointer(&input, &ctx.canvas().unwrap()).unwrap();
+    new_emulator(ctx, conf, input)
+}
+
+fn init_keyboard(input: &Rc<RefCell<Input>>, keys: &KeyMap) -> Result<(), JsValue> {
+    let window = web_sys::window().unwrap();
+    let key_down = make_key_callback(input.clone(), keys.clone(), true).into_js_value();
+    window.add_event_listener_with_callback("keydown", key_down.unchecked_ref::<Function>())?;
+    let key_up = make_key_callback(input.clone(), keys.clone(), false).into_js_value();
+    window.add_event_listener_with_callback("keyup", key_up.unchecked_ref::<Function>())?;
+
+    let input = input.clone();
+    let blur = Closure::wrap(Box::new(move || {
+        input.borrow_mut().release_keys();
+    }) as Box<dyn FnMut()>).into_js_value();
+    window.add_event_listener_with_callback("blur", blur.unchecked_ref::<Function>())?;
+    Ok(())
+}
+
+fn make_key_callback(input: Rc<RefCell<Input>>, keys: KeyMap, pressed: bool)
+    -> Closure<dyn FnMut(KeyboardEvent)> {
+
+    Closure::wrap(Box::new(move |event: KeyboardEvent| {
+        let code = event.code();
+        let device = input.borrow().device();
+        // All the keys are typed on the Family BASIC keyboard, without driving the controllers
+        if device == Device::FamilyKeyboard {
+            if let Some((row, bit)) = keys.find_family_key(code.as_str()) {
+                // Keeps the function keys and the editing keys from acting on the page
+                event.prevent_default();
+                input.borrow_mut().press_family_key(row, bit, pressed);
+            }
+            return;
+        }
+        if let Some((player, button)) = keys.find(code.as_str()) {
+            // Keeps the arrow keys and the space from scrolling the page
+            event.prevent_default();
+            input.borrow_mut().press_key(player, button, pressed);
+        }
+        if device.has_mat() {
+            if let Some(position) = keys.find_mat(code.as_str()) {
+                event.prevent_default();
+                input.borrow_mut().press_mat(position, pressed);
+            }
+        }
+        if keys.is_mic(code.as_str()) {
+            input.borrow_mut().press_mic(pressed);
+        }
+    }) as Box<dyn FnMut(KeyboardEvent)>)
+}
+
+// The mouse and the touch on the canvas drive the light gun and the paddle
+fn init_pointer(input: &Rc<RefCell<Input>>, canvas: &HtmlCanvasElement) -> Result<(), JsValue> {
+    let mouse_listeners: [(&str, MouseHandler); 4] = [
+        ("mousemove", |input, point, _| input.set_pointer(point)),
+        ("mousedown", |input, point, event| if event.button() == 0 {
+            input.set_pointer(point);
+            input.press_pointer(true);
+        }),
+        ("mouseup", |input, _, event| if event.button() == 0 {
+            input.press_pointer(false);
+        }),
+        ("mouseleave", |input, _, _| {
+            input.set_pointer(None);
+            input.press_pointer(false);
+        })
+    ];
+    for (name, f) in mouse_listeners.iter() {
+        let callback = make_mouse_callback(input.clone(), canvas.clone(), *f).into_js_value();
+        canvas.add_event_listener_with_callback(name, callback.unchecked_ref::<Function>())?;
+    }
+
+    let touch_listeners: [(&str, TouchHandler); 4] = [
+        ("touchstart", |input, point| {
+            input.set_pointer(point);
+            input.press_pointer(true);
+        }),
+        ("touchmove", |input, point| input.set_pointer(point)),
+        ("touchend", |input, _| input.press_pointer(false)),
+        ("touchcancel", |input, _| input.press_pointer(false))
+    ];
+    for (name, f) in touch_listeners.iter() {
+        let callback = make_touch_callback(input.clone(), canvas.clone(), *f).into_js_value();
+        canvas.add_event_listener_with_callback(name, callback.unchecked_ref::<Function>())?;
+    }
+    Ok(())
+}
+
+fn make_mouse_callback(input: Rc<RefCell<Input>>, canvas: HtmlCanvasElement,
+                       f: MouseHandler) -> Closure<dyn FnMut(MouseEvent)> {
+    Closure::wrap(Box::new(move |event: MouseEvent| {
+        let point = screen_point(&canvas, event.client_x() as f64, event.client_y() as f64);
+        f(&mut input.borrow_mut(), point, &event);
+    }) as Box<dyn FnMut(MouseEvent)>)
+}
+
+fn make_touch_callback(input: Rc<RefCell<Input>>, canvas: HtmlCanvasElement,
+                       f: TouchHandler) -> Closure<dyn FnMut(TouchEvent)> {
+    Closure::wrap(Box::new(move |event: TouchEvent| {
+        // Keeps the touch from scrolling the page or emulating the mouse
+        event.prevent_default();
+        let point = event.target_touches().get(0).and_then(|t| {
+            screen_point(&canvas, t.client_x() as f64, t.client_y() as f64)
+        });
+        f(&mut input.borrow_mut(), point);
+    }) as Box<dyn FnMut(TouchEvent)>)
+}
+
+// The canvas is scaled by CSS in make_resize_callback,
+// so the point is mapped to the NES pixel by the size the canvas is displayed in.
+fn screen_point(canvas: &HtmlCanvasElement, client_x: f64, client_y: f64) -> Option<(u8, u8)> {
+    let rect = canvas.get_bounding_client_rect();
+    scale_point(client_x - rect.left(), client_y - rect.top(), rect.width(), rect.height())
+}
+
+fn scale_point(x: f64, y: f64, width: f64, height: f64) -> Option<(u8, u8)> {
+    if x < 0.0 || y < 0.0 || x >= width || y >= height {
+        None
+    } else {
+        Some(((x * RAW_WIDTH as f64 / width) as u8, (y * RAW_HEIGHT as f64 / height) as u8))
+    }
 }
 
 fn init_canvas(element: &HtmlElement, conf: &Configuration)
@@ -135,3 +263,17 @@ fn get_context_2d(canvas: &HtmlCanvasElement, conf: &Configuration)
         Err(e) => Err(e)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scale_point() {
+        assert_eq!(scale_point(0.0, 0.0, 512.0, 480.0), Some((0, 0)));
+        assert_eq!(scale_point(511.0, 479.0, 512.0, 480.0), Some((255, 239)));
+        assert_eq!(scale_point(300.0, 100.0, 768.0, 720.0), Some((100, 33)));
+        assert_eq!(scale_point(-1.0, 10.0, 512.0, 480.0), None);
+        assert_eq!(scale_point(10.0, 480.0, 512.0, 480.0), None);
+    }
+}
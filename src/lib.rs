@@ -2,4 +2,5 @@ mod init;
 mod conf;
 mod emulator;
 mod i18n;
-mod rom;
\ No newline at end of file
+mod rom;
+mod state;
\ No newline at end of file
@@ -0,0 +1,55 @@
+use super::slice::Slice;
+use super::ram::Ram;
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
+// Pattern table storage of a cartridge, either CHR-ROM or CHR-RAM
+pub enum Chr {
+    Rom(Slice),
+    Ram(Ram)
+}
+
+impl Chr {
+    pub fn new(chr_rom: Option<Slice>, chr_ram_size: u32) -> Chr {
+        match chr_rom {
+            Some(s) => Chr::Rom(s),
+            None => Chr::Ram(Ram::new(chr_ram_size))
+        }
+    }
+
+    pub fn length(&self) -> u32 {
+        match self {
+            Chr::Rom(s) => s.length(),
+            Chr::Ram(r) => r.length()
+        }
+    }
+
+    pub fn at(&self, index: u32) -> u8 {
+        match self {
+            Chr::Rom(s) => s.at(index),
+            Chr::Ram(r) => r.at(index)
+        }
+    }
+
+    // CHR-ROM ignores writes
+    pub fn set(&mut self, index: u32, value: u8) {
+        if let Chr::Ram(r) = self {
+            r.set(index, value);
+        }
+    }
+}
+
+impl Stateful for Chr {
+    fn save_state(&self, w: &mut StateWriter) {
+        if let Chr::Ram(ram) = self {
+            ram.save_state(w);
+        }
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        match self {
+            Chr::Ram(ram) => ram.load_state(r),
+            Chr::Rom(_) => Ok(())
+        }
+    }
+}
@@ -1,29 +1,136 @@
 use super::slice::Slice;
+use super::ram::Ram;
+use super::Mirroring;
+use crate::state::Stateful;
 
-pub trait Mapper {
-    fn read_prg(&self, addr: u16) -> u8;
+// What the PPU is going to fetch during rendering
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PpuFetch {
+    // The background tile of the pixel x in the current scanline
+    Background(u8),
+    Sprite
+}
+
+// Mappers save their bank registers and the cartridge RAM into the state
+pub trait Mapper: Stateful {
+    // PRG-ROM space $8000-$FFFF
+    fn read_prg(&mut self, addr: u16) -> u8;
     fn write_prg(&mut self, addr: u16, value: u8);
     fn read_chr(&self, addr: u16) -> u8;
     fn write_chr(&mut self, addr: u16, value: u8);
-}
 
-pub fn find_mapper(mapper: u16, _sub_mapper: Option<u8>,
-                   prg_rom: Slice, chr_rom: Option<Slice>) -> Option<Box<dyn Mapper>> {
-    if mapper == 3 {
-        Some(Box::new(super::mappers::Mapper003::new(prg_rom, chr_rom.unwrap())))
-    } else {
+    // Expansion area $4020-$5FFF. None means nothing drives the data bus.
+    fn read_expansion(&mut self, _addr: u16) -> Option<u8> {
+        None
+    }
+
+    fn write_expansion(&mut self, _addr: u16, _value: u8) {}
+
+    // The PRG-RAM on the cartridge, including the battery-backed part
+    fn prg_ram_mut(&mut self) -> Option<&mut Ram> {
+        None
+    }
+
+    // Work RAM space $6000-$7FFF. None means nothing drives the data bus.
+    // RAM smaller than 8K is mirrored through the whole space.
+    fn read_ram(&mut self, addr: u16) -> Option<u8> {
+        match self.prg_ram_mut() {
+            Some(ram) if ram.length() > 0 => Some(ram.at((addr as u32 - 0x6000) % ram.length())),
+            _ => None
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        match self.prg_ram_mut() {
+            Some(ram) if ram.length() > 0 => {
+                let index = (addr as u32 - 0x6000) % ram.length();
+                ram.set(index, value);
+            },
+            _ => ()
+        }
+    }
+
+    // The nametable mirroring selected by the mapper.
+    // None means the mirroring is fixed by the header.
+    fn mirroring(&self) -> Option<Mirroring> {
         None
     }
+
+    // Nametable access when the mirroring is Mirroring::Mapped,
+    // the address is in $0000-$0FFF and vram is the 2K VRAM of the console.
+    fn read_nametable(&self, addr: u16, vram: &[u8]) -> u8 {
+        vram[(addr & 0x07FF) as usize]
+    }
+
+    fn write_nametable(&mut self, addr: u16, value: u8, vram: &mut [u8]) {
+        vram[(addr & 0x07FF) as usize] = value;
+    }
+
+    // Called with every address the PPU puts on its address bus
+    fn notify_ppu_address(&mut self, _addr: u16) {}
+
+    // Called every PPU dot, before the addresses of the dot are notified
+    fn notify_ppu_tick(&mut self) {}
+
+    // Called with every CPU write to the PPU registers, as the mapper may snoop them
+    fn notify_ppu_register(&mut self, _addr: u16, _value: u8) {}
+
+    // Called when the PPU starts rendering a visible scanline,
+    // or with None when the PPU is not rendering or leaves the visible frame.
+    fn notify_scanline(&mut self, _line: Option<u8>) {}
+
+    // Called before the PPU fetches background or sprite data during rendering
+    fn notify_fetch(&mut self, _fetch: PpuFetch) {}
+
+    // Whether the mapper is asserting the IRQ line
+    fn irq(&self) -> bool {
+        false
+    }
+}
+
+// The bus conflicts of the boards having them as an option are decided by the submapper,
+// unless they're turned on or off by the configuration
+pub fn find_mapper(mapper: u16, sub_mapper: Option<u8>, bus_conflicts: Option<bool>,
+                   prg_rom: Slice, chr_rom: Option<Slice>,
+                   prg_ram_size: u32, chr_ram_size: u32) -> Option<Box<dyn Mapper>> {
+    match mapper {
+        0 => Some(Box::new(super::mappers::Mapper000::new(prg_rom, chr_rom,
+                                                          prg_ram_size, chr_ram_size))),
+        1 => Some(Box::new(super::mappers::Mapper001::new(prg_rom, chr_rom, sub_mapper,
+                                                          prg_ram_size, chr_ram_size))),
+        2 => Some(Box::new(super::mappers::Mapper002::new(prg_rom, chr_rom,
+                                                          prg_ram_size, chr_ram_size))),
+        3 => Some(Box::new(super::mappers::Mapper003::new(prg_rom, chr_rom,
+                                                          prg_ram_size, chr_ram_size))),
+        4 => Some(Box::new(super::mappers::Mapper004::new(prg_rom, chr_rom,
+                                                          prg_ram_size, chr_ram_size))),
+        5 => Some(Box::new(super::mappers::Mapper005::new(prg_rom, chr_rom,
+                                                          prg_ram_size, chr_ram_size))),
+        7 => Some(Box::new(super::mappers::Mapper007::new(prg_rom, chr_rom,
+                                                          bus_conflicts.unwrap_or(sub_mapper == Some(2)),
+                                                          prg_ram_size, chr_ram_size))),
+        _ => None
+    }
 }
 
 #[cfg(test)]
 pub(crate) mod tests {
     use super::*;
+    use crate::state::{StateWriter, StateReader};
+    use crate::i18n::Message;
 
     struct MockMapper;
 
+    impl Stateful for MockMapper {
+        fn save_state(&self, _w: &mut StateWriter) {}
+
+        fn load_state(&mut self, _r: &mut StateReader) -> Result<(), Message> {
+            Ok(())
+        }
+    }
+
     impl Mapper for MockMapper {
-        fn read_prg(&self, _addr: u16) -> u8 {
+        fn read_prg(&mut self, _addr: u16) -> u8 {
             1
         }
 
@@ -43,4 +150,20 @@ pub(crate) mod tests {
     pub fn mock() -> Box<dyn Mapper> {
         Box::new(MockMapper)
     }
+
+    #[test]
+    fn test_bus_conflicts() {
+        // Writing $13 where the ROM has $03 selects bank 3 with the lower nametable on conflicts
+        let conflicts = |sub_mapper, bus_conflicts| {
+            let prg_rom = crate::rom::tests::make_slice(256 * 1024, 1024);
+            let mut mapper = find_mapper(7, sub_mapper, bus_conflicts, prg_rom, None, 0, 8 * 1024).unwrap();
+            mapper.write_prg(0x8C00, 0x13);
+            mapper.mirroring() == Some(Mirroring::SingleScreenLower)
+        };
+        assert!(conflicts(Some(2), None));
+        assert!(!conflicts(Some(1), None));
+        assert!(!conflicts(None, None));
+        assert!(conflicts(None, Some(true)));
+        assert!(!conflicts(Some(2), Some(false)));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,133 @@
+use crate::rom::mapper::Mapper;
+use crate::rom::slice::Slice;
+use crate::rom::ram::Ram;
+use crate::rom::chr::Chr;
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
+pub struct Mapper000 {
+    prg_rom: Slice,
+    prg_ram: Option<Ram>,
+    chr: Chr
+}
+
+impl Mapper000 {
+    pub fn new(prg_rom: Slice, chr_rom: Option<Slice>,
+               prg_ram_size: u32, chr_ram_size: u32) -> Mapper000 {
+        let prg_ram = if prg_ram_size > 0 {
+            Some(Ram::new(prg_ram_size))
+        } else {
+            None
+        };
+        Mapper000 {
+            prg_rom,
+            prg_ram,
+            chr: Chr::new(chr_rom, chr_ram_size)
+        }
+    }
+}
+
+impl Mapper for Mapper000 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        // 16K PRG-ROM is mirrored into $C000-$FFFF
+        self.prg_rom.at((addr as u32 - 0x8000) % self.prg_rom.length())
+    }
+
+    fn write_prg(&mut self, _addr: u16, _value: u8) {}
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.at(addr as u32 % self.chr.length())
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        self.chr.set(addr as u32 % self.chr.length(), value);
+    }
+
+    // Family BASIC 2K/4K PRG-RAM is mirrored through $6000-$7FFF
+    fn prg_ram_mut(&mut self) -> Option<&mut Ram> {
+        self.prg_ram.as_mut()
+    }
+}
+
+impl Stateful for Mapper000 {
+    fn save_state(&self, w: &mut StateWriter) {
+        if let Some(ram) = self.prg_ram.as_ref() {
+            ram.save_state(w);
+        }
+        self.chr.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        if let Some(ram) = self.prg_ram.as_mut() {
+            ram.load_state(r)?;
+        }
+        self.chr.load_state(r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::make_slice;
+
+    const K: u32 = 1024;
+    const CHR_SIZE: u32 = 8 * K;
+
+    #[test]
+    fn test_prg_16k() {
+        let mut mapper = Mapper000::new(make_slice(16 * K, K), Some(make_slice(CHR_SIZE, K)), 0, 0);
+        for addr in [0x8000u16, 0x8123, 0xBFFF] {
+            assert_eq!(mapper.read_prg(addr), mapper.prg_rom.at(addr as u32 - 0x8000));
+            assert_eq!(mapper.read_prg(addr + 0x4000), mapper.read_prg(addr));
+        }
+    }
+
+    #[test]
+    fn test_prg_32k() {
+        let mut mapper = Mapper000::new(make_slice(32 * K, K), Some(make_slice(CHR_SIZE, K)), 0, 0);
+        for addr in [0x8000u16, 0xA000, 0xC000, 0xFFFF] {
+            assert_eq!(mapper.read_prg(addr), mapper.prg_rom.at(addr as u32 - 0x8000));
+        }
+        assert_ne!(mapper.read_prg(0x8000), mapper.read_prg(0xC001));
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut mapper = Mapper000::new(make_slice(16 * K, K), None, 0, CHR_SIZE);
+        mapper.write_ram(0x6000, 0xAA);
+        assert_eq!(mapper.read_ram(0x6000), None);
+
+        let mut mapper = Mapper000::new(make_slice(16 * K, K), None, 2 * K, CHR_SIZE);
+        mapper.write_ram(0x6000, 0xAA);
+        mapper.write_ram(0x6801, 0xBB);
+        assert_eq!(mapper.read_ram(0x6000), Some(0xAA));
+        assert_eq!(mapper.read_ram(0x7000), Some(0xAA));
+        assert_eq!(mapper.read_ram(0x6001), Some(0xBB));
+        assert_eq!(mapper.read_ram(0x7801), Some(0xBB));
+
+        let mut mapper = Mapper000::new(make_slice(16 * K, K), None, 4 * K, CHR_SIZE);
+        mapper.write_ram(0x6801, 0xCC);
+        assert_eq!(mapper.read_ram(0x6801), Some(0xCC));
+        assert_eq!(mapper.read_ram(0x6001), Some(0));
+        assert_eq!(mapper.read_ram(0x7801), Some(0xCC));
+    }
+
+    #[test]
+    fn test_chr_rom() {
+        let mut mapper = Mapper000::new(make_slice(16 * K, K), Some(make_slice(CHR_SIZE, K)), 0, 0);
+        let v = mapper.read_chr(0x1234);
+        assert_eq!(v, 0x04);
+        mapper.write_chr(0x1234, v + 1);
+        assert_eq!(mapper.read_chr(0x1234), v);
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        let mut mapper = Mapper000::new(make_slice(16 * K, K), None, 0, CHR_SIZE);
+        for addr in [0x0000u16, 0x0FFF, 0x1000, 0x1FFF] {
+            let v = rand::random::<u8>();
+            mapper.write_chr(addr, v);
+            assert_eq!(mapper.read_chr(addr), v);
+        }
+    }
+}
@@ -0,0 +1,401 @@
+use crate::rom::mapper::Mapper;
+use crate::rom::slice::Slice;
+use crate::rom::ram::Ram;
+use crate::rom::chr::Chr;
+use crate::rom::Mirroring;
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
+const K: u32 = 1024;
+const PRG_BANK_SIZE: u32 = 16 * K;
+const CHR_BANK_SIZE: u32 = 4 * K;
+const PRG_RAM_BANK_SIZE: u32 = 8 * K;
+
+// The MMC1 boards which use the CHR bank registers for other purposes
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Board {
+    Standard,
+    // CHR bank bit 4 disables PRG-RAM
+    Snrom,
+    // CHR bank bit 3 selects a 8K bank of the 16K PRG-RAM
+    Sorom,
+    // CHR bank bit 4 selects a 256K bank of the 512K PRG-ROM
+    Surom,
+    // Both SUROM banking and 32K PRG-RAM banked by CHR bank bits 2-3
+    Sxrom
+}
+
+pub struct Mapper001 {
+    prg_rom: Slice,
+    prg_ram: Ram,
+    chr: Chr,
+    board: Board,
+
+    shift: u8,
+    shift_count: u8,
+
+    control: u8,
+    chr_bank_0: u8,
+    chr_bank_1: u8,
+    prg_bank: u8
+}
+
+impl Mapper001 {
+    pub fn new(prg_rom: Slice, chr_rom: Option<Slice>,
+               sub_mapper: Option<u8>, prg_ram_size: u32, chr_ram_size: u32) -> Mapper001 {
+        let board = select_board(sub_mapper, prg_rom.length(), chr_rom.is_none(), prg_ram_size);
+        let prg_ram_size = match board {
+            Board::Sorom => 16 * K,
+            Board::Sxrom => 32 * K,
+            _ => prg_ram_size
+        };
+        Mapper001 {
+            prg_rom,
+            prg_ram: Ram::new(prg_ram_size),
+            chr: Chr::new(chr_rom, chr_ram_size),
+            board,
+            shift: 0,
+            shift_count: 0,
+            // PRG mode 3 after power on, with the last bank fixed at $C000
+            control: 0x0C,
+            chr_bank_0: 0,
+            chr_bank_1: 0,
+            prg_bank: 0
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, value: u8) {
+        match addr & 0xE000 {
+            0x8000 => self.control = value,
+            0xA000 => self.chr_bank_0 = value,
+            0xC000 => self.chr_bank_1 = value,
+            _ => self.prg_bank = value
+        }
+    }
+
+    fn prg_ram_enabled(&self) -> bool {
+        if self.prg_ram.length() == 0 || self.prg_bank & 0x10 != 0 {
+            false
+        } else if self.board == Board::Snrom {
+            self.chr_bank_0 & 0x10 == 0
+        } else {
+            true
+        }
+    }
+
+    fn prg_ram_index(&self, addr: u16) -> u32 {
+        let bank = match self.board {
+            Board::Sorom => (self.chr_bank_0 >> 3) & 0x01,
+            Board::Sxrom => (self.chr_bank_0 >> 2) & 0x03,
+            _ => 0
+        };
+        (bank as u32 * PRG_RAM_BANK_SIZE + (addr as u32 - 0x6000)) % self.prg_ram.length()
+    }
+
+    fn prg_rom_index(&self, addr: u16) -> u32 {
+        let outer = match self.board {
+            Board::Surom | Board::Sxrom => self.chr_bank_0 & 0x10,
+            _ => 0
+        };
+        let bank = self.prg_bank & 0x0F;
+        let offset = addr as u32 & 0x3FFF;
+        let index = match (self.control >> 2) & 0x03 {
+            // 32K mode ignores the low bit of the bank number
+            0 | 1 => if addr < 0xC000 {
+                outer | (bank & 0x0E)
+            } else {
+                outer | (bank & 0x0E) | 0x01
+            },
+            2 => if addr < 0xC000 {
+                outer
+            } else {
+                outer | bank
+            },
+            _ => if addr < 0xC000 {
+                outer | bank
+            } else {
+                outer | 0x0F
+            }
+        };
+        (index as u32 * PRG_BANK_SIZE + offset) % self.prg_rom.length()
+    }
+
+    fn chr_index(&self, addr: u16) -> u32 {
+        let index = if self.control & 0x10 == 0 {
+            // 8K mode ignores the low bit of the bank number
+            (self.chr_bank_0 & 0x1E) as u32 * CHR_BANK_SIZE + addr as u32
+        } else if addr < 0x1000 {
+            self.chr_bank_0 as u32 * CHR_BANK_SIZE + addr as u32
+        } else {
+            self.chr_bank_1 as u32 * CHR_BANK_SIZE + (addr as u32 - 0x1000)
+        };
+        index % self.chr.length()
+    }
+}
+
+fn select_board(sub_mapper: Option<u8>, prg_rom_size: u32, chr_ram: bool, prg_ram_size: u32) -> Board {
+    match sub_mapper {
+        Some(1) => Board::Surom,
+        Some(2) => Board::Sorom,
+        Some(4) => Board::Sxrom,
+        _ => if prg_ram_size > 16 * K {
+            Board::Sxrom
+        } else if prg_rom_size > 256 * K {
+            Board::Surom
+        } else if prg_ram_size > 8 * K {
+            Board::Sorom
+        } else if chr_ram {
+            Board::Snrom
+        } else {
+            Board::Standard
+        }
+    }
+}
+
+impl Mapper for Mapper001 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.prg_rom.at(self.prg_rom_index(addr))
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if value & 0x80 != 0 {
+            self.shift = 0;
+            self.shift_count = 0;
+            self.control |= 0x0C;
+        } else {
+            self.shift |= (value & 0x01) << self.shift_count;
+            self.shift_count += 1;
+            if self.shift_count == 5 {
+                self.write_register(addr, self.shift);
+                self.shift = 0;
+                self.shift_count = 0;
+            }
+        }
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut Ram> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn read_ram(&mut self, addr: u16) -> Option<u8> {
+        if self.prg_ram_enabled() {
+            Some(self.prg_ram.at(self.prg_ram_index(addr)))
+        } else {
+            None
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if self.prg_ram_enabled() {
+            let index = self.prg_ram_index(addr);
+            self.prg_ram.set(index, value);
+        }
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.at(self.chr_index(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let index = self.chr_index(addr);
+        self.chr.set(index, value);
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(match self.control & 0x03 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal
+        })
+    }
+}
+
+impl Stateful for Mapper001 {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.prg_ram.save_state(w);
+        self.chr.save_state(w);
+        w.write_u8(self.shift);
+        w.write_u8(self.shift_count);
+        w.write_u8(self.control);
+        w.write_u8(self.chr_bank_0);
+        w.write_u8(self.chr_bank_1);
+        w.write_u8(self.prg_bank);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.prg_ram.load_state(r)?;
+        self.chr.load_state(r)?;
+        self.shift = r.read_u8()?;
+        self.shift_count = r.read_u8()?;
+        self.control = r.read_u8()?;
+        self.chr_bank_0 = r.read_u8()?;
+        self.chr_bank_1 = r.read_u8()?;
+        self.prg_bank = r.read_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::make_slice;
+
+    fn write_serial(mapper: &mut Mapper001, addr: u16, value: u8) {
+        for i in 0..5 {
+            mapper.write_prg(addr, (value >> i) & 0x01);
+        }
+    }
+
+    #[test]
+    fn test_shift_register() {
+        let mut mapper = Mapper001::new(make_slice(128 * K, 4 * K), Some(make_slice(128 * K, 4 * K)), None, 8 * K, 8 * K);
+        write_serial(&mut mapper, 0xA000, 0x15);
+        assert_eq!(mapper.chr_bank_0, 0x15);
+        write_serial(&mut mapper, 0xDFFF, 0x0A);
+        assert_eq!(mapper.chr_bank_1, 0x0A);
+        write_serial(&mut mapper, 0xE000, 0x03);
+        assert_eq!(mapper.prg_bank, 0x03);
+
+        mapper.write_prg(0x8000, 0x01);
+        mapper.write_prg(0x8000, 0x01);
+        mapper.write_prg(0x8000, 0x80);
+        assert_eq!(mapper.shift_count, 0);
+        assert_eq!(mapper.control & 0x0C, 0x0C);
+        write_serial(&mut mapper, 0x8000, 0x00);
+        assert_eq!(mapper.control, 0x00);
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mapper = Mapper001::new(make_slice(128 * K, 4 * K), Some(make_slice(8 * K, 4 * K)), None, 8 * K, 8 * K);
+        // Fix last bank
+        write_serial(&mut mapper, 0xE000, 0x02);
+        assert_eq!(mapper.read_prg(0x8000), 8);
+        assert_eq!(mapper.read_prg(0xC000), 28);
+
+        // Fix first bank
+        write_serial(&mut mapper, 0x8000, 0x08);
+        assert_eq!(mapper.read_prg(0x8000), 0);
+        assert_eq!(mapper.read_prg(0xFFFF), 11);
+
+        // 32K
+        write_serial(&mut mapper, 0x8000, 0x00);
+        write_serial(&mut mapper, 0xE000, 0x05);
+        assert_eq!(mapper.read_prg(0x8000), 16);
+        assert_eq!(mapper.read_prg(0xC000), 20);
+    }
+
+    #[test]
+    fn test_chr_modes() {
+        let mut mapper = Mapper001::new(make_slice(32 * K, 4 * K), Some(make_slice(32 * K, 4 * K)), None, 8 * K, 8 * K);
+        write_serial(&mut mapper, 0xA000, 0x03);
+        write_serial(&mut mapper, 0xC000, 0x05);
+        // 8K
+        assert_eq!(mapper.read_chr(0x0000), 2);
+        assert_eq!(mapper.read_chr(0x1000), 3);
+
+        // 4K
+        write_serial(&mut mapper, 0x8000, 0x1C);
+        assert_eq!(mapper.read_chr(0x0000), 3);
+        assert_eq!(mapper.read_chr(0x1FFF), 5);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mapper = Mapper001::new(make_slice(32 * K, 4 * K), Some(make_slice(8 * K, 4 * K)), None, 8 * K, 8 * K);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::SingleScreenLower));
+        write_serial(&mut mapper, 0x8000, 0x01);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::SingleScreenUpper));
+        write_serial(&mut mapper, 0x8000, 0x02);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::Vertical));
+        write_serial(&mut mapper, 0x8000, 0x03);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::Horizontal));
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut mapper = Mapper001::new(make_slice(32 * K, 4 * K), Some(make_slice(8 * K, 4 * K)), None, 8 * K, 8 * K);
+        mapper.write_ram(0x6010, 0x55);
+        assert_eq!(mapper.read_ram(0x6010), Some(0x55));
+
+        write_serial(&mut mapper, 0xE000, 0x10);
+        assert_eq!(mapper.read_ram(0x6010), None);
+        mapper.write_ram(0x6010, 0xAA);
+        write_serial(&mut mapper, 0xE000, 0x00);
+        assert_eq!(mapper.read_ram(0x6010), Some(0x55));
+    }
+
+    #[test]
+    fn test_snrom() {
+        let mut mapper = Mapper001::new(make_slice(256 * K, 4 * K), None, None, 8 * K, 8 * K);
+        assert_eq!(mapper.board, Board::Snrom);
+        mapper.write_chr(0x1234, 0x66);
+        assert_eq!(mapper.read_chr(0x1234), 0x66);
+
+        mapper.write_ram(0x7000, 0x77);
+        write_serial(&mut mapper, 0xA000, 0x10);
+        assert_eq!(mapper.read_ram(0x7000), None);
+        write_serial(&mut mapper, 0xA000, 0x00);
+        assert_eq!(mapper.read_ram(0x7000), Some(0x77));
+    }
+
+    #[test]
+    fn test_sorom() {
+        let mut mapper = Mapper001::new(make_slice(256 * K, 4 * K), None, Some(2), 0, 8 * K);
+        assert_eq!(mapper.board, Board::Sorom);
+        mapper.write_ram(0x6000, 0x11);
+        write_serial(&mut mapper, 0xA000, 0x08);
+        assert_eq!(mapper.read_ram(0x6000), Some(0));
+        mapper.write_ram(0x6000, 0x22);
+        write_serial(&mut mapper, 0xA000, 0x00);
+        assert_eq!(mapper.read_ram(0x6000), Some(0x11));
+    }
+
+    #[test]
+    fn test_surom() {
+        let mut mapper = Mapper001::new(make_slice(512 * K, 4 * K), None, None, 8 * K, 8 * K);
+        assert_eq!(mapper.board, Board::Surom);
+        assert_eq!(mapper.read_prg(0xC000), 60);
+        write_serial(&mut mapper, 0xA000, 0x10);
+        assert_eq!(mapper.read_prg(0x8000), 64);
+        assert_eq!(mapper.read_prg(0xC000), 124);
+    }
+
+    #[test]
+    fn test_sxrom() {
+        let mut mapper = Mapper001::new(make_slice(512 * K, 4 * K), None, Some(4), 0, 8 * K);
+        assert_eq!(mapper.board, Board::Sxrom);
+        for bank in 0..4u8 {
+            write_serial(&mut mapper, 0xA000, bank << 2);
+            mapper.write_ram(0x6000, bank + 1);
+        }
+        for bank in 0..4u8 {
+            write_serial(&mut mapper, 0xA000, 0x10 | (bank << 2));
+            assert_eq!(mapper.read_ram(0x6000), Some(bank + 1));
+            assert_eq!(mapper.read_prg(0x8000), 64);
+        }
+    }
+
+    #[test]
+    fn test_state() {
+        let mut mapper = Mapper001::new(make_slice(256 * K, 4 * K), None, None, 8 * K, 8 * K);
+        write_serial(&mut mapper, 0xE000, 0x03);
+        mapper.write_ram(0x6000, 0x12);
+        mapper.write_chr(0x0123, 0x34);
+        // Stopped in the middle of the shift register
+        mapper.write_prg(0x8000, 0x01);
+        let mut w = StateWriter::new();
+        mapper.save_state(&mut w);
+        let data = w.finish();
+
+        let mut other = Mapper001::new(make_slice(256 * K, 4 * K), None, None, 8 * K, 8 * K);
+        let mut r = StateReader::new(&data).ok().unwrap();
+        assert!(other.load_state(&mut r).is_ok());
+        assert!(r.finished());
+        assert_eq!(other.read_prg(0x8000), 12);
+        assert_eq!(other.read_ram(0x6000), Some(0x12));
+        assert_eq!(other.read_chr(0x0123), 0x34);
+        assert_eq!(other.shift_count, mapper.shift_count);
+    }
+}
@@ -0,0 +1,123 @@
+use crate::rom::mapper::Mapper;
+use crate::rom::slice::Slice;
+use crate::rom::ram::Ram;
+use crate::rom::chr::Chr;
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
+const K: u32 = 1024;
+const PRG_BANK_SIZE: u32 = 16 * K;
+
+pub struct Mapper002 {
+    prg_rom: Slice,
+    prg_ram: Ram,
+    chr: Chr,
+    bank: u8
+}
+
+impl Mapper002 {
+    pub fn new(prg_rom: Slice, chr_rom: Option<Slice>,
+               prg_ram_size: u32, chr_ram_size: u32) -> Mapper002 {
+        Mapper002 {
+            prg_rom,
+            prg_ram: Ram::new(prg_ram_size),
+            chr: Chr::new(chr_rom, chr_ram_size),
+            bank: 0
+        }
+    }
+}
+
+impl Mapper for Mapper002 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        if addr >= 0xC000 {
+            let last = self.prg_rom.length() - PRG_BANK_SIZE;
+            self.prg_rom.at(last + (addr as u32 - 0xC000))
+        } else {
+            let index = self.bank as u32 * PRG_BANK_SIZE + (addr as u32 - 0x8000);
+            self.prg_rom.at(index % self.prg_rom.length())
+        }
+    }
+
+    fn write_prg(&mut self, _addr: u16, value: u8) {
+        self.bank = value;
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.at(addr as u32 % self.chr.length())
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        self.chr.set(addr as u32 % self.chr.length(), value);
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut Ram> {
+        Some(&mut self.prg_ram)
+    }
+}
+
+impl Stateful for Mapper002 {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.prg_ram.save_state(w);
+        self.chr.save_state(w);
+        w.write_u8(self.bank);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.prg_ram.load_state(r)?;
+        self.chr.load_state(r)?;
+        self.bank = r.read_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::make_slice;
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mapper = Mapper002::new(make_slice(128 * K, PRG_BANK_SIZE), None, 0, 8 * K);
+        assert_eq!(mapper.read_prg(0x8000), 0);
+        assert_eq!(mapper.read_prg(0xC000), 7);
+        assert_eq!(mapper.read_prg(0xFFFF), 7);
+
+        mapper.write_prg(0x8000, 3);
+        assert_eq!(mapper.read_prg(0x8000), 3);
+        assert_eq!(mapper.read_prg(0xBFFF), 3);
+        assert_eq!(mapper.read_prg(0xC000), 7);
+
+        mapper.write_prg(0xFFFF, 0x0A);
+        assert_eq!(mapper.read_prg(0x8000), 2);
+        assert_eq!(mapper.read_prg(0xC000), 7);
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        let mut mapper = Mapper002::new(make_slice(128 * K, PRG_BANK_SIZE), None, 0, 8 * K);
+        for addr in [0x0000u16, 0x0ABC, 0x1000, 0x1FFF] {
+            let v = rand::random::<u8>();
+            mapper.write_chr(addr, v);
+            assert_eq!(mapper.read_chr(addr), v);
+        }
+    }
+
+    #[test]
+    fn test_state() {
+        let mut mapper = Mapper002::new(make_slice(128 * K, PRG_BANK_SIZE), None, 8 * K, 8 * K);
+        mapper.write_prg(0x8000, 5);
+        mapper.write_ram(0x6000, 0x12);
+        mapper.write_chr(0x0123, 0x34);
+        let mut w = StateWriter::new();
+        mapper.save_state(&mut w);
+        let data = w.finish();
+
+        let mut other = Mapper002::new(make_slice(128 * K, PRG_BANK_SIZE), None, 8 * K, 8 * K);
+        let mut r = StateReader::new(&data).ok().unwrap();
+        assert!(other.load_state(&mut r).is_ok());
+        assert!(r.finished());
+        assert_eq!(other.read_prg(0x8000), 5);
+        assert_eq!(other.read_ram(0x6000), Some(0x12));
+        assert_eq!(other.read_chr(0x0123), 0x34);
+    }
+}
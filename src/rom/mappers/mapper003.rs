@@ -1,18 +1,23 @@
 use crate::rom::mapper::Mapper;
 use crate::rom::slice::Slice;
+use crate::rom::ram::Ram;
+use crate::rom::chr::Chr;
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
 
 const K: u16 = 1024;
 const BANK_SIZE: u16 = 8 * K;
 
 pub struct Mapper003 {
     prg_rom: Slice,
-    chr_rom: Slice,
+    chr: Chr,
+    prg_ram: Ram,
     bank: u8,
     read_prg: fn(s: &Slice, addr: u16) -> u8
 }
 
 impl Mapper003 {
-    pub fn new(prg_rom: Slice, chr_rom: Slice) -> Mapper003 {
+    pub fn new(prg_rom: Slice, chr_rom: Option<Slice>, prg_ram_size: u32, chr_ram_size: u32) -> Mapper003 {
         let read_prg_fn = if prg_rom.length() == 32 * K as u32 {
             read_prg_32k
         } else {
@@ -20,15 +25,20 @@ impl Mapper003 {
         };
         Mapper003 {
             prg_rom,
-            chr_rom,
+            chr: Chr::new(chr_rom, chr_ram_size),
+            prg_ram: Ram::new(prg_ram_size),
             bank: 0,
             read_prg: read_prg_fn
         }
     }
+
+    fn chr_index(&self, addr: u16) -> u32 {
+        (self.bank as u32 * BANK_SIZE as u32 + addr as u32) % self.chr.length()
+    }
 }
 
 impl Mapper for Mapper003 {
-    fn read_prg(&self, addr: u16) -> u8 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
         (self.read_prg)(&self.prg_rom, addr)
     }
 
@@ -37,10 +47,33 @@ impl Mapper for Mapper003 {
     }
 
     fn read_chr(&self, addr: u16) -> u8 {
-        self.chr_rom.at(self.bank as u32 * BANK_SIZE as u32 + addr as u32)
+        self.chr.at(self.chr_index(addr))
+    }
+
+    // Boards with CHR-RAM instead of CHR-ROM
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let index = self.chr_index(addr);
+        self.chr.set(index, value);
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut Ram> {
+        Some(&mut self.prg_ram)
     }
+}
 
-    fn write_chr(&mut self, _addr: u16, _value: u8) {}
+impl Stateful for Mapper003 {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.prg_ram.save_state(w);
+        self.chr.save_state(w);
+        w.write_u8(self.bank);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.prg_ram.load_state(r)?;
+        self.chr.load_state(r)?;
+        self.bank = r.read_u8()?;
+        Ok(())
+    }
 }
 
 fn read_prg_32k(s: &Slice, addr: u16) -> u8 {
@@ -49,4 +82,44 @@ fn read_prg_32k(s: &Slice, addr: u16) -> u8 {
 
 fn read_prg_16k(s: &Slice, addr: u16) -> u8 {
     s.at((addr as u32 - 0x8000) & 0x3FFF)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::make_slice;
+
+    // 32K PRG-ROM and 4 banks of CHR-ROM filled with the bank numbers
+    fn make_mapper() -> Mapper003 {
+        let size = 32 * K as u32;
+        Mapper003::new(make_slice(size, size), Some(make_slice(size, BANK_SIZE as u32)), 8 * K as u32, 0)
+    }
+
+    #[test]
+    fn test_state() {
+        let mut mapper = make_mapper();
+        mapper.write_prg(0x8000, 2);
+        mapper.write_ram(0x6000, 0x12);
+        let mut w = StateWriter::new();
+        mapper.save_state(&mut w);
+        let data = w.finish();
+
+        let mut other = make_mapper();
+        let mut r = StateReader::new(&data).ok().unwrap();
+        assert!(other.load_state(&mut r).is_ok());
+        assert!(r.finished());
+        assert_eq!(other.read_chr(0x0123), 2);
+        assert_eq!(other.read_ram(0x6000), Some(0x12));
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        let size = 32 * K as u32;
+        let mut mapper = Mapper003::new(make_slice(size, size), None, 0, 8 * K as u32);
+        for addr in [0x0000u16, 0x0ABC, 0x1000, 0x1FFF] {
+            let v = rand::random::<u8>();
+            mapper.write_chr(addr, v);
+            assert_eq!(mapper.read_chr(addr), v);
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,400 @@
+use crate::rom::mapper::Mapper;
+use crate::rom::slice::Slice;
+use crate::rom::ram::Ram;
+use crate::rom::chr::Chr;
+use crate::rom::Mirroring;
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
+const K: u32 = 1024;
+const PRG_BANK_SIZE: u32 = 8 * K;
+const CHR_BANK_SIZE: u32 = K;
+// A rise of A12 only clocks the IRQ counter after A12 has been low for about 3 CPU cycles,
+// since the M2 filter of MMC3 ignores the short falls between the nametable and pattern fetches
+const A12_FILTER_DOTS: u8 = 10;
+
+pub struct Mapper004 {
+    prg_rom: Slice,
+    prg_ram: Ram,
+    chr: Chr,
+
+    bank_select: u8,
+    banks: [u8; 8],
+    mirroring: u8,
+    prg_ram_protect: u8,
+
+    irq_latch: u8,
+    irq_counter: u8,
+    irq_reload: bool,
+    irq_enabled: bool,
+    irq_pending: bool,
+    a12: bool,
+    // The PPU dots since A12 fell
+    a12_low: u8
+}
+
+impl Mapper004 {
+    pub fn new(prg_rom: Slice, chr_rom: Option<Slice>,
+               prg_ram_size: u32, chr_ram_size: u32) -> Mapper004 {
+        Mapper004 {
+            prg_rom,
+            prg_ram: Ram::new(prg_ram_size),
+            chr: Chr::new(chr_rom, chr_ram_size),
+            bank_select: 0,
+            banks: [0; 8],
+            mirroring: 0,
+            prg_ram_protect: 0x80,
+            irq_latch: 0,
+            irq_counter: 0,
+            irq_reload: false,
+            irq_enabled: false,
+            irq_pending: false,
+            a12: false,
+            // A12 has been low since power on
+            a12_low: u8::MAX
+        }
+    }
+
+    fn prg_rom_index(&self, addr: u16) -> u32 {
+        let count = (self.prg_rom.length() / PRG_BANK_SIZE) as u8;
+        // 16K PRG-ROM or less has no distinct second-last bank
+        let second_last = count.saturating_sub(2);
+        let bank = match (addr & 0xE000, self.bank_select & 0x40 != 0) {
+            (0x8000, false) | (0xC000, true) => self.banks[6],
+            (0x8000, true) | (0xC000, false) => second_last,
+            (0xA000, _) => self.banks[7],
+            _ => count - 1
+        };
+        (bank as u32 % count as u32) * PRG_BANK_SIZE + (addr as u32 & 0x1FFF)
+    }
+
+    fn chr_index(&self, addr: u16) -> u32 {
+        // CHR A12 inversion swaps the 2K banks and the 1K banks
+        let addr = if self.bank_select & 0x80 != 0 {
+            addr ^ 0x1000
+        } else {
+            addr
+        };
+        let index = match addr & 0x1C00 {
+            0x0000 | 0x0400 => (self.banks[0] & 0xFE) as u32 * CHR_BANK_SIZE + (addr as u32 & 0x07FF),
+            0x0800 | 0x0C00 => (self.banks[1] & 0xFE) as u32 * CHR_BANK_SIZE + (addr as u32 & 0x07FF),
+            n => self.banks[(n >> 10) as usize - 2] as u32 * CHR_BANK_SIZE + (addr as u32 & 0x03FF)
+        };
+        index % self.chr.length()
+    }
+
+    fn clock_irq_counter(&mut self) {
+        if self.irq_counter == 0 || self.irq_reload {
+            self.irq_counter = self.irq_latch;
+            self.irq_reload = false;
+        } else {
+            self.irq_counter -= 1;
+        }
+        if self.irq_counter == 0 && self.irq_enabled {
+            self.irq_pending = true;
+        }
+    }
+}
+
+impl Mapper for Mapper004 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.prg_rom.at(self.prg_rom_index(addr))
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        let even = addr & 0x01 == 0;
+        match addr & 0xE000 {
+            0x8000 => if even {
+                self.bank_select = value;
+            } else {
+                self.banks[(self.bank_select & 0x07) as usize] = value;
+            },
+            0xA000 => if even {
+                self.mirroring = value & 0x01;
+            } else {
+                self.prg_ram_protect = value;
+            },
+            0xC000 => if even {
+                self.irq_latch = value;
+            } else {
+                self.irq_counter = 0;
+                self.irq_reload = true;
+            },
+            _ => if even {
+                self.irq_enabled = false;
+                self.irq_pending = false;
+            } else {
+                self.irq_enabled = true;
+            }
+        }
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.at(self.chr_index(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let index = self.chr_index(addr);
+        self.chr.set(index, value);
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut Ram> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn read_ram(&mut self, addr: u16) -> Option<u8> {
+        if self.prg_ram_protect & 0x80 != 0 && self.prg_ram.length() > 0 {
+            Some(self.prg_ram.at((addr as u32 - 0x6000) % self.prg_ram.length()))
+        } else {
+            None
+        }
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        if self.prg_ram_protect & 0xC0 == 0x80 && self.prg_ram.length() > 0 {
+            let index = (addr as u32 - 0x6000) % self.prg_ram.length();
+            self.prg_ram.set(index, value);
+        }
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        if self.mirroring == 0 {
+            Some(Mirroring::Vertical)
+        } else {
+            Some(Mirroring::Horizontal)
+        }
+    }
+
+    fn notify_ppu_address(&mut self, addr: u16) {
+        let a12 = addr & 0x1000 != 0;
+        if a12 && !self.a12 && self.a12_low >= A12_FILTER_DOTS {
+            self.clock_irq_counter();
+        } else if !a12 && self.a12 {
+            self.a12_low = 0;
+        }
+        self.a12 = a12;
+    }
+
+    fn notify_ppu_tick(&mut self) {
+        if !self.a12 {
+            self.a12_low = self.a12_low.saturating_add(1);
+        }
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_pending
+    }
+}
+
+impl Stateful for Mapper004 {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.prg_ram.save_state(w);
+        self.chr.save_state(w);
+        w.write_u8(self.bank_select);
+        w.write_bytes(&self.banks);
+        w.write_u8(self.mirroring);
+        w.write_u8(self.prg_ram_protect);
+        w.write_u8(self.irq_latch);
+        w.write_u8(self.irq_counter);
+        w.write_bool(self.irq_reload);
+        w.write_bool(self.irq_enabled);
+        w.write_bool(self.irq_pending);
+        w.write_bool(self.a12);
+        w.write_u8(self.a12_low);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.prg_ram.load_state(r)?;
+        self.chr.load_state(r)?;
+        self.bank_select = r.read_u8()?;
+        r.read_bytes(&mut self.banks)?;
+        self.mirroring = r.read_u8()?;
+        self.prg_ram_protect = r.read_u8()?;
+        self.irq_latch = r.read_u8()?;
+        self.irq_counter = r.read_u8()?;
+        self.irq_reload = r.read_bool()?;
+        self.irq_enabled = r.read_bool()?;
+        self.irq_pending = r.read_bool()?;
+        self.a12 = r.read_bool()?;
+        self.a12_low = r.read_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::make_slice;
+
+    fn make_mapper() -> Mapper004 {
+        Mapper004::new(make_slice(128 * K, K), Some(make_slice(128 * K, K)), 8 * K, 0)
+    }
+
+    fn scanline(mapper: &mut Mapper004) {
+        mapper.notify_ppu_address(0x0000);
+        for _ in 0..A12_FILTER_DOTS {
+            mapper.notify_ppu_tick();
+        }
+        mapper.notify_ppu_address(0x1000);
+        mapper.notify_ppu_address(0x1008);
+    }
+
+    // The addresses of a rendered scanline with the background patterns at $1000
+    // and the sprite patterns at $0000
+    fn render_line(mapper: &mut Mapper004) {
+        for dot in 0..341u16 {
+            mapper.notify_ppu_tick();
+            if (1..257).contains(&dot) {
+                mapper.notify_ppu_address(0x2000 + dot / 8);
+                mapper.notify_ppu_address(0x23C0);
+                mapper.notify_ppu_address(0x1000 + dot % 8);
+                mapper.notify_ppu_address(0x1008 + dot % 8);
+            } else if dot == 257 {
+                mapper.notify_ppu_address(0x0FF0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mapper = make_mapper();
+        mapper.write_prg(0x8000, 0x06);
+        mapper.write_prg(0x8001, 0x03);
+        mapper.write_prg(0x8000, 0x07);
+        mapper.write_prg(0x8001, 0x05);
+
+        assert_eq!(mapper.read_prg(0x8000), 24);
+        assert_eq!(mapper.read_prg(0xA000), 40);
+        assert_eq!(mapper.read_prg(0xC000), 112);
+        assert_eq!(mapper.read_prg(0xE000), 120);
+
+        mapper.write_prg(0x8000, 0x40);
+        assert_eq!(mapper.read_prg(0x8000), 112);
+        assert_eq!(mapper.read_prg(0xA000), 40);
+        assert_eq!(mapper.read_prg(0xC000), 24);
+        assert_eq!(mapper.read_prg(0xFFFF), 127);
+    }
+
+    #[test]
+    fn test_small_prg() {
+        let mut mapper = Mapper004::new(make_slice(8 * K, K), Some(make_slice(8 * K, K)), 0, 0);
+        for addr in [0x8000, 0xA000, 0xC000, 0xE000] {
+            assert_eq!(mapper.read_prg(addr), 0);
+        }
+        mapper.write_prg(0x8000, 0x40);
+        assert_eq!(mapper.read_prg(0x8000), 0);
+    }
+
+    #[test]
+    fn test_chr_inversion() {
+        let mut mapper = make_mapper();
+        for i in 0..8u8 {
+            mapper.write_prg(0x8000, i);
+            mapper.write_prg(0x8001, 10 + i * 2 + 1);
+        }
+
+        assert_eq!(mapper.read_chr(0x0000), 10);
+        assert_eq!(mapper.read_chr(0x0400), 11);
+        assert_eq!(mapper.read_chr(0x0800), 12);
+        assert_eq!(mapper.read_chr(0x0C00), 13);
+        assert_eq!(mapper.read_chr(0x1000), 15);
+        assert_eq!(mapper.read_chr(0x1400), 17);
+        assert_eq!(mapper.read_chr(0x1800), 19);
+        assert_eq!(mapper.read_chr(0x1C00), 21);
+
+        mapper.write_prg(0x8000, 0x80);
+        assert_eq!(mapper.read_chr(0x0000), 15);
+        assert_eq!(mapper.read_chr(0x0C00), 21);
+        assert_eq!(mapper.read_chr(0x1000), 10);
+        assert_eq!(mapper.read_chr(0x1C00), 13);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mapper = make_mapper();
+        mapper.write_prg(0xA000, 0x00);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::Vertical));
+        mapper.write_prg(0xA000, 0x01);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::Horizontal));
+    }
+
+    #[test]
+    fn test_prg_ram_protect() {
+        let mut mapper = make_mapper();
+        mapper.write_ram(0x6000, 0x12);
+        assert_eq!(mapper.read_ram(0x6000), Some(0x12));
+
+        mapper.write_prg(0xA001, 0xC0);
+        mapper.write_ram(0x6000, 0x34);
+        assert_eq!(mapper.read_ram(0x6000), Some(0x12));
+
+        mapper.write_prg(0xA001, 0x00);
+        assert_eq!(mapper.read_ram(0x6000), None);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut mapper = make_mapper();
+        mapper.write_prg(0xC000, 3);
+        mapper.write_prg(0xC001, 0);
+        mapper.write_prg(0xE001, 0);
+
+        // Reload, 2, 1, 0
+        for _ in 0..3 {
+            scanline(&mut mapper);
+            assert!(!mapper.irq());
+        }
+        scanline(&mut mapper);
+        assert!(mapper.irq());
+
+        mapper.write_prg(0xE000, 0);
+        assert!(!mapper.irq());
+        // Reloaded from the latch since the counter is 0
+        scanline(&mut mapper);
+        assert_eq!(mapper.irq_counter, 3);
+        assert!(!mapper.irq());
+    }
+
+    #[test]
+    fn test_a12_filter() {
+        let mut mapper = make_mapper();
+        mapper.write_prg(0xC000, 200);
+        mapper.write_prg(0xC001, 0);
+        // The first line reloads the counter
+        render_line(&mut mapper);
+        assert_eq!(mapper.irq_counter, 200);
+        for line in 1..10 {
+            render_line(&mut mapper);
+            assert_eq!(mapper.irq_counter, 200 - line);
+        }
+
+        // A short fall is ignored
+        mapper.notify_ppu_address(0x1000);
+        assert_eq!(mapper.irq_counter, 190);
+        mapper.notify_ppu_address(0x0000);
+        mapper.notify_ppu_tick();
+        mapper.notify_ppu_address(0x1000);
+        assert_eq!(mapper.irq_counter, 190);
+    }
+
+    #[test]
+    fn test_state() {
+        let mut mapper = make_mapper();
+        mapper.write_prg(0x8000, 0x46);
+        mapper.write_prg(0x8001, 0x03);
+        mapper.write_prg(0xC000, 5);
+        mapper.write_ram(0x6000, 0x12);
+        scanline(&mut mapper);
+        let mut w = StateWriter::new();
+        mapper.save_state(&mut w);
+        let data = w.finish();
+
+        let mut other = make_mapper();
+        let mut r = StateReader::new(&data).ok().unwrap();
+        assert!(other.load_state(&mut r).is_ok());
+        assert!(r.finished());
+        assert_eq!(other.read_prg(0xC000), 24);
+        assert_eq!(other.read_ram(0x6000), Some(0x12));
+        assert_eq!(other.irq_counter, mapper.irq_counter);
+    }
+}
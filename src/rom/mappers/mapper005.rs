@@ -0,0 +1,691 @@
+use crate::rom::mapper::{Mapper, PpuFetch};
+use crate::rom::slice::Slice;
+use crate::rom::ram::Ram;
+use crate::rom::chr::Chr;
+use crate::rom::Mirroring;
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
+const K: u32 = 1024;
+const PRG_BANK_SIZE: u32 = 8 * K;
+const EXRAM_SIZE: usize = K as usize;
+
+// ExRAM modes, mode 0 uses ExRAM as an extra nametable
+const EXRAM_ATTRIBUTE: u8 = 1;
+const EXRAM_READ_WRITE: u8 = 2;
+
+enum Prg {
+    Rom(u32),
+    Ram(u32),
+    None
+}
+
+pub struct Mapper005 {
+    prg_rom: Slice,
+    prg_ram: Ram,
+    chr: Chr,
+    exram: [u8; EXRAM_SIZE],
+
+    prg_mode: u8,
+    chr_mode: u8,
+    prg_ram_protect: [u8; 2],
+    exram_mode: u8,
+    nametables: u8,
+    fill_tile: u8,
+    fill_attribute: u8,
+    prg_banks: [u8; 5],
+    // Sprite banks $5120-$5127 and background banks $5128-$512B, with the upper bits from $5130
+    chr_a: [u16; 8],
+    chr_b: [u16; 4],
+    chr_upper: u8,
+    last_chr_b: bool,
+
+    split_control: u8,
+    split_scroll: u8,
+    split_bank: u8,
+
+    irq_compare: u8,
+    irq_enabled: bool,
+    irq_pending: bool,
+    in_frame: bool,
+    scanline: u8,
+
+    multiplicand: u8,
+    multiplier: u8,
+
+    // The PPU state snooped by the mapper
+    large_sprite: bool,
+    fetch: PpuFetch,
+    last_tile: u16
+}
+
+impl Mapper005 {
+    pub fn new(prg_rom: Slice, chr_rom: Option<Slice>,
+               prg_ram_size: u32, chr_ram_size: u32) -> Mapper005 {
+        Mapper005 {
+            prg_rom,
+            prg_ram: Ram::new(prg_ram_size),
+            chr: Chr::new(chr_rom, chr_ram_size),
+            exram: [0; EXRAM_SIZE],
+            prg_mode: 3,
+            chr_mode: 0,
+            prg_ram_protect: [0; 2],
+            exram_mode: 0,
+            nametables: 0,
+            fill_tile: 0,
+            fill_attribute: 0,
+            prg_banks: [0, 0xFF, 0xFF, 0xFF, 0xFF],
+            chr_a: [0; 8],
+            chr_b: [0; 4],
+            chr_upper: 0,
+            last_chr_b: false,
+            split_control: 0,
+            split_scroll: 0,
+            split_bank: 0,
+            irq_compare: 0,
+            irq_enabled: false,
+            irq_pending: false,
+            in_frame: false,
+            scanline: 0,
+            multiplicand: 0xFF,
+            multiplier: 0xFF,
+            large_sprite: false,
+            fetch: PpuFetch::Sprite,
+            last_tile: 0
+        }
+    }
+
+    // Returns the register which maps the address and the size of the bank in 8K units
+    fn prg_bank(&self, addr: u16) -> (u8, u8) {
+        match (self.prg_mode, addr & 0xE000) {
+            (0, _) => (self.prg_banks[4], 4),
+            (1, 0x8000) | (1, 0xA000) => (self.prg_banks[2], 2),
+            (1, _) => (self.prg_banks[4], 2),
+            (2, 0x8000) | (2, 0xA000) => (self.prg_banks[2], 2),
+            (2, 0xC000) => (self.prg_banks[3], 1),
+            (2, _) => (self.prg_banks[4], 1),
+            (_, a) => (self.prg_banks[((a - 0x8000) >> 13) as usize + 1], 1)
+        }
+    }
+
+    fn prg_index(&self, addr: u16) -> Prg {
+        if addr < 0x8000 {
+            return self.prg_ram_index(self.prg_banks[0], addr as u32 - 0x6000);
+        }
+        let (bank, size) = self.prg_bank(addr);
+        // $5117 always maps ROM
+        let rom = self.prg_mode == 0 || (self.prg_mode == 1 && addr >= 0xC000)
+            || addr >= 0xE000 || bank & 0x80 != 0;
+        let bank = bank & 0x7F & !(size - 1);
+        let offset = (addr as u32 - 0x8000) % (size as u32 * PRG_BANK_SIZE);
+        if rom {
+            let index = bank as u32 * PRG_BANK_SIZE + offset;
+            Prg::Rom(index % self.prg_rom.length())
+        } else {
+            self.prg_ram_index(bank, offset)
+        }
+    }
+
+    fn prg_ram_index(&self, bank: u8, offset: u32) -> Prg {
+        if self.prg_ram.length() == 0 {
+            Prg::None
+        } else {
+            let index = (bank & 0x07) as u32 * PRG_BANK_SIZE + offset;
+            Prg::Ram(index % self.prg_ram.length())
+        }
+    }
+
+    fn prg_ram_writable(&self) -> bool {
+        self.prg_ram_protect == [0x02, 0x01]
+    }
+
+    fn split_region(&self) -> bool {
+        if self.split_control & 0x80 == 0 || self.exram_mode > EXRAM_ATTRIBUTE {
+            return false;
+        }
+        match self.fetch {
+            PpuFetch::Background(x) => {
+                let tile = x / 8;
+                let threshold = self.split_control & 0x1F;
+                if self.split_control & 0x40 == 0 {
+                    tile < threshold
+                } else {
+                    tile >= threshold
+                }
+            },
+            PpuFetch::Sprite => false
+        }
+    }
+
+    fn split_y(&self) -> u16 {
+        let y = self.split_scroll as u16 + self.scanline as u16;
+        if y >= 240 {
+            y - 240
+        } else {
+            y
+        }
+    }
+
+    fn chr_index(&self, addr: u16) -> u32 {
+        let background = matches!(self.fetch, PpuFetch::Background(_)) && self.in_frame;
+        let index = if background && self.split_region() {
+            let fine_y = self.split_y() as u32 & 0x07;
+            self.split_bank as u32 * 4 * K + (addr as u32 & 0x0FF8) + fine_y
+        } else if background && self.exram_mode == EXRAM_ATTRIBUTE {
+            let bank = (self.exram[self.last_tile as usize] & 0x3F) as u32 | ((self.chr_upper as u32) << 6);
+            bank * 4 * K + (addr as u32 & 0x0FFF)
+        } else {
+            let set_b = if self.large_sprite && self.in_frame {
+                background
+            } else {
+                self.last_chr_b
+            };
+            let addr = addr as u32;
+            if set_b {
+                match self.chr_mode {
+                    0 => self.chr_b[3] as u32 * 8 * K + addr,
+                    1 => self.chr_b[3] as u32 * 4 * K + (addr & 0x0FFF),
+                    2 => self.chr_b[((addr >> 11) as usize & 0x01) * 2 + 1] as u32 * 2 * K + (addr & 0x07FF),
+                    _ => self.chr_b[(addr >> 10) as usize & 0x03] as u32 * K + (addr & 0x03FF)
+                }
+            } else {
+                match self.chr_mode {
+                    0 => self.chr_a[7] as u32 * 8 * K + addr,
+                    1 => self.chr_a[(addr >> 12) as usize * 4 + 3] as u32 * 4 * K + (addr & 0x0FFF),
+                    2 => self.chr_a[(addr >> 11) as usize * 2 + 1] as u32 * 2 * K + (addr & 0x07FF),
+                    _ => self.chr_a[(addr >> 10) as usize] as u32 * K + (addr & 0x03FF)
+                }
+            }
+        };
+        index % self.chr.length()
+    }
+
+    fn prg_ram_read(&self, prg: Prg) -> Option<u8> {
+        match prg {
+            Prg::Ram(index) => Some(self.prg_ram.at(index)),
+            _ => None
+        }
+    }
+
+    fn prg_ram_write(&mut self, prg: Prg, value: u8) {
+        if let Prg::Ram(index) = prg {
+            if self.prg_ram_writable() {
+                self.prg_ram.set(index, value);
+            }
+        }
+    }
+}
+
+impl Mapper for Mapper005 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        match self.prg_index(addr) {
+            Prg::Rom(index) => self.prg_rom.at(index),
+            Prg::Ram(index) => self.prg_ram.at(index),
+            Prg::None => 0
+        }
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        if addr < 0xE000 {
+            self.prg_ram_write(self.prg_index(addr), value);
+        }
+    }
+
+    fn read_expansion(&mut self, addr: u16) -> Option<u8> {
+        match addr {
+            0x5204 => {
+                let mut status = 0;
+                if self.irq_pending {
+                    status |= 0x80;
+                }
+                if self.in_frame {
+                    status |= 0x40;
+                }
+                self.irq_pending = false;
+                Some(status)
+            },
+            0x5205 => Some((self.multiplicand as u16 * self.multiplier as u16) as u8),
+            0x5206 => Some(((self.multiplicand as u16 * self.multiplier as u16) >> 8) as u8),
+            0x5C00..=0x5FFF if self.exram_mode >= EXRAM_READ_WRITE => Some(self.exram[(addr - 0x5C00) as usize]),
+            _ => None
+        }
+    }
+
+    fn write_expansion(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x5100 => self.prg_mode = value & 0x03,
+            0x5101 => self.chr_mode = value & 0x03,
+            0x5102 => self.prg_ram_protect[0] = value & 0x03,
+            0x5103 => self.prg_ram_protect[1] = value & 0x03,
+            0x5104 => self.exram_mode = value & 0x03,
+            0x5105 => self.nametables = value,
+            0x5106 => self.fill_tile = value,
+            0x5107 => self.fill_attribute = value & 0x03,
+            0x5113..=0x5117 => self.prg_banks[(addr - 0x5113) as usize] = value,
+            0x5120..=0x5127 => {
+                self.chr_a[(addr - 0x5120) as usize] = value as u16 | (self.chr_upper as u16) << 8;
+                self.last_chr_b = false;
+            },
+            0x5128..=0x512B => {
+                self.chr_b[(addr - 0x5128) as usize] = value as u16 | (self.chr_upper as u16) << 8;
+                self.last_chr_b = true;
+            },
+            0x5130 => self.chr_upper = value & 0x03,
+            0x5200 => self.split_control = value,
+            0x5201 => self.split_scroll = value,
+            0x5202 => self.split_bank = value,
+            0x5203 => self.irq_compare = value,
+            0x5204 => self.irq_enabled = value & 0x80 != 0,
+            0x5205 => self.multiplicand = value,
+            0x5206 => self.multiplier = value,
+            0x5C00..=0x5FFF => {
+                let index = (addr - 0x5C00) as usize;
+                if self.exram_mode == EXRAM_READ_WRITE {
+                    self.exram[index] = value;
+                } else if self.exram_mode < EXRAM_READ_WRITE {
+                    // Only writable while rendering in the nametable modes
+                    self.exram[index] = if self.in_frame { value } else { 0 };
+                }
+            },
+            _ => ()
+        }
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut Ram> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn read_ram(&mut self, addr: u16) -> Option<u8> {
+        self.prg_ram_read(self.prg_index(addr))
+    }
+
+    fn write_ram(&mut self, addr: u16, value: u8) {
+        self.prg_ram_write(self.prg_index(addr), value);
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.at(self.chr_index(addr))
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        let index = self.chr_index(addr);
+        self.chr.set(index, value);
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        Some(Mirroring::Mapped)
+    }
+
+    fn read_nametable(&self, addr: u16, vram: &[u8]) -> u8 {
+        let offset = addr & 0x03FF;
+        let attribute = offset >= 0x03C0;
+        if self.in_frame && self.split_region() {
+            let x = match self.fetch {
+                PpuFetch::Background(x) => x as u16,
+                PpuFetch::Sprite => 0
+            };
+            let y = self.split_y();
+            return if attribute {
+                let attr = self.exram[(0x03C0 + y / 32 * 8 + x / 32) as usize];
+                let shift = (x / 16 % 2) * 2 + (y / 16 % 2) * 4;
+                ((attr >> shift) & 0x03) * 0x55
+            } else {
+                self.exram[(y / 8 * 32 + x / 8) as usize]
+            };
+        }
+        if attribute && self.exram_mode == EXRAM_ATTRIBUTE && self.in_frame {
+            return (self.exram[self.last_tile as usize] >> 6) * 0x55;
+        }
+
+        match (self.nametables >> (((addr >> 10) & 0x03) * 2)) & 0x03 {
+            0 => vram[offset as usize],
+            1 => vram[(0x0400 | offset) as usize],
+            2 => if self.exram_mode <= EXRAM_ATTRIBUTE {
+                self.exram[offset as usize]
+            } else {
+                0
+            },
+            _ => if attribute {
+                self.fill_attribute * 0x55
+            } else {
+                self.fill_tile
+            }
+        }
+    }
+
+    fn write_nametable(&mut self, addr: u16, value: u8, vram: &mut [u8]) {
+        let offset = addr & 0x03FF;
+        match (self.nametables >> (((addr >> 10) & 0x03) * 2)) & 0x03 {
+            0 => vram[offset as usize] = value,
+            1 => vram[(0x0400 | offset) as usize] = value,
+            2 if self.exram_mode <= EXRAM_ATTRIBUTE => self.exram[offset as usize] = value,
+            _ => ()
+        }
+    }
+
+    fn notify_ppu_address(&mut self, addr: u16) {
+        if (0x2000..0x3F00).contains(&addr) && addr & 0x03FF < 0x03C0 {
+            self.last_tile = addr & 0x03FF;
+        }
+    }
+
+    fn notify_ppu_register(&mut self, addr: u16, value: u8) {
+        if addr & 0x2007 == 0x2000 {
+            self.large_sprite = value & 0x20 != 0;
+        }
+    }
+
+    fn notify_scanline(&mut self, line: Option<u8>) {
+        match line {
+            Some(l) => {
+                if self.in_frame {
+                    self.scanline = self.scanline.wrapping_add(1);
+                    if self.scanline == self.irq_compare {
+                        self.irq_pending = true;
+                    }
+                } else {
+                    self.in_frame = true;
+                    self.scanline = l;
+                }
+            },
+            None => {
+                self.in_frame = false;
+                self.irq_pending = false;
+            }
+        }
+    }
+
+    fn notify_fetch(&mut self, fetch: PpuFetch) {
+        self.fetch = fetch;
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_enabled && self.irq_pending
+    }
+}
+
+impl Stateful for Mapper005 {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.prg_ram.save_state(w);
+        self.chr.save_state(w);
+        w.write_bytes(&self.exram);
+
+        for v in [self.prg_mode, self.chr_mode, self.prg_ram_protect[0], self.prg_ram_protect[1],
+                  self.exram_mode, self.nametables, self.fill_tile, self.fill_attribute] {
+            w.write_u8(v);
+        }
+        w.write_bytes(&self.prg_banks);
+        for v in self.chr_a.iter().chain(self.chr_b.iter()) {
+            w.write_u16(*v);
+        }
+        w.write_u8(self.chr_upper);
+        w.write_bool(self.last_chr_b);
+
+        w.write_u8(self.split_control);
+        w.write_u8(self.split_scroll);
+        w.write_u8(self.split_bank);
+
+        w.write_u8(self.irq_compare);
+        w.write_bool(self.irq_enabled);
+        w.write_bool(self.irq_pending);
+        w.write_bool(self.in_frame);
+        w.write_u8(self.scanline);
+
+        w.write_u8(self.multiplicand);
+        w.write_u8(self.multiplier);
+
+        w.write_bool(self.large_sprite);
+        match self.fetch {
+            PpuFetch::Background(x) => {
+                w.write_bool(true);
+                w.write_u8(x);
+            },
+            PpuFetch::Sprite => {
+                w.write_bool(false);
+                w.write_u8(0);
+            }
+        }
+        w.write_u16(self.last_tile);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.prg_ram.load_state(r)?;
+        self.chr.load_state(r)?;
+        r.read_bytes(&mut self.exram)?;
+
+        self.prg_mode = r.read_u8()?;
+        self.chr_mode = r.read_u8()?;
+        self.prg_ram_protect[0] = r.read_u8()?;
+        self.prg_ram_protect[1] = r.read_u8()?;
+        self.exram_mode = r.read_u8()?;
+        self.nametables = r.read_u8()?;
+        self.fill_tile = r.read_u8()?;
+        self.fill_attribute = r.read_u8()?;
+        r.read_bytes(&mut self.prg_banks)?;
+        for v in self.chr_a.iter_mut().chain(self.chr_b.iter_mut()) {
+            *v = r.read_u16()?;
+        }
+        self.chr_upper = r.read_u8()?;
+        self.last_chr_b = r.read_bool()?;
+
+        self.split_control = r.read_u8()?;
+        self.split_scroll = r.read_u8()?;
+        self.split_bank = r.read_u8()?;
+
+        self.irq_compare = r.read_u8()?;
+        self.irq_enabled = r.read_bool()?;
+        self.irq_pending = r.read_bool()?;
+        self.in_frame = r.read_bool()?;
+        self.scanline = r.read_u8()?;
+
+        self.multiplicand = r.read_u8()?;
+        self.multiplier = r.read_u8()?;
+
+        self.large_sprite = r.read_bool()?;
+        let background = r.read_bool()?;
+        let x = r.read_u8()?;
+        self.fetch = if background {
+            PpuFetch::Background(x)
+        } else {
+            PpuFetch::Sprite
+        };
+        self.last_tile = r.read_u16()? & 0x03FF;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::make_slice;
+
+    fn make_mapper() -> Mapper005 {
+        Mapper005::new(make_slice(256 * K, K), Some(make_slice(256 * K, K)), 64 * K, 0)
+    }
+
+    #[test]
+    fn test_prg_modes() {
+        let mut mapper = make_mapper();
+        mapper.write_expansion(0x5114, 0x81);
+        assert_eq!(mapper.read_prg(0x8000), 8);
+        assert_eq!(mapper.read_prg(0xE000), 248);
+
+        mapper.write_expansion(0x5100, 0);
+        mapper.write_expansion(0x5117, 0x87);
+        assert_eq!(mapper.read_prg(0x8000), 32);
+        assert_eq!(mapper.read_prg(0xE000), 56);
+
+        mapper.write_expansion(0x5100, 1);
+        mapper.write_expansion(0x5115, 0x83);
+        assert_eq!(mapper.read_prg(0x8000), 16);
+        assert_eq!(mapper.read_prg(0xC000), 48);
+
+        mapper.write_expansion(0x5100, 2);
+        mapper.write_expansion(0x5116, 0x85);
+        assert_eq!(mapper.read_prg(0xA000), 24);
+        assert_eq!(mapper.read_prg(0xC000), 40);
+        assert_eq!(mapper.read_prg(0xE000), 56);
+    }
+
+    #[test]
+    fn test_prg_ram() {
+        let mut mapper = make_mapper();
+        mapper.write_expansion(0x5113, 1);
+        mapper.write_ram(0x6000, 0x12);
+        assert_eq!(mapper.read_ram(0x6000), Some(0));
+
+        mapper.write_expansion(0x5102, 0x02);
+        mapper.write_expansion(0x5103, 0x01);
+        mapper.write_ram(0x6000, 0x12);
+        assert_eq!(mapper.read_ram(0x6000), Some(0x12));
+
+        // RAM bank 1 mapped at $8000
+        mapper.write_expansion(0x5114, 0x01);
+        assert_eq!(mapper.read_prg(0x8000), 0x12);
+        mapper.write_prg(0x8001, 0x34);
+        assert_eq!(mapper.read_ram(0x6001), Some(0x34));
+    }
+
+    #[test]
+    fn test_chr_sets() {
+        let mut mapper = make_mapper();
+        mapper.write_expansion(0x5101, 3);
+        for i in 0..8 {
+            mapper.write_expansion(0x5120 + i, 10 + i as u8);
+        }
+        for i in 0..4 {
+            mapper.write_expansion(0x5128 + i, 20 + i as u8);
+        }
+        // The last written set is used outside of 8x16 rendering
+        assert_eq!(mapper.read_chr(0x0000), 20);
+        assert_eq!(mapper.read_chr(0x0C00), 23);
+        assert_eq!(mapper.read_chr(0x1000), 20);
+        mapper.write_expansion(0x5123, 13);
+        assert_eq!(mapper.read_chr(0x0400), 11);
+
+        mapper.notify_ppu_register(0x2000, 0x20);
+        mapper.notify_scanline(Some(0));
+        mapper.notify_fetch(PpuFetch::Background(0));
+        assert_eq!(mapper.read_chr(0x0400), 21);
+        mapper.notify_fetch(PpuFetch::Sprite);
+        assert_eq!(mapper.read_chr(0x0400), 11);
+
+        mapper.notify_scanline(None);
+        mapper.write_expansion(0x5101, 1);
+        mapper.write_expansion(0x5123, 3);
+        mapper.write_expansion(0x5127, 5);
+        assert_eq!(mapper.read_chr(0x0000), 12);
+        assert_eq!(mapper.read_chr(0x1000), 20);
+    }
+
+    #[test]
+    fn test_nametables() {
+        let mut mapper = make_mapper();
+        let mut vram = [0u8; 0x0800];
+        mapper.write_expansion(0x5105, 0b11_10_01_00);
+        mapper.write_expansion(0x5106, 0x42);
+        mapper.write_expansion(0x5107, 0x02);
+
+        mapper.write_nametable(0x0000, 1, &mut vram);
+        mapper.write_nametable(0x0400, 2, &mut vram);
+        mapper.write_nametable(0x0800, 3, &mut vram);
+        assert_eq!(vram[0x0000], 1);
+        assert_eq!(vram[0x0400], 2);
+        assert_eq!(mapper.exram[0], 3);
+        assert_eq!(mapper.read_nametable(0x0800, &vram), 3);
+
+        assert_eq!(mapper.read_nametable(0x0C00, &vram), 0x42);
+        assert_eq!(mapper.read_nametable(0x0FC0, &vram), 0xAA);
+    }
+
+    #[test]
+    fn test_extended_attribute() {
+        let mut mapper = make_mapper();
+        let vram = [0u8; 0x0800];
+        mapper.write_expansion(0x5104, 2);
+        mapper.write_expansion(0x5C05, 0xC3);
+        assert_eq!(mapper.read_expansion(0x5C05), Some(0xC3));
+        mapper.write_expansion(0x5104, 1);
+
+        mapper.notify_scanline(Some(0));
+        mapper.notify_fetch(PpuFetch::Background(40));
+        mapper.notify_ppu_address(0x2005);
+        assert_eq!(mapper.read_nametable(0x03C1, &vram), 0xFF);
+        assert_eq!(mapper.read_chr(0x0010), 12);
+    }
+
+    #[test]
+    fn test_split() {
+        let mut mapper = make_mapper();
+        let vram = [0u8; 0x0800];
+        mapper.write_expansion(0x5104, 2);
+        mapper.write_expansion(0x5C01, 0x33);
+        mapper.write_expansion(0x5FC0, 0xE4);
+        mapper.write_expansion(0x5104, 0);
+        mapper.write_expansion(0x5200, 0x84);
+        mapper.write_expansion(0x5202, 1);
+
+        mapper.notify_scanline(Some(0));
+        mapper.notify_fetch(PpuFetch::Background(8));
+        assert_eq!(mapper.read_nametable(0x0000, &vram), 0x33);
+        mapper.notify_fetch(PpuFetch::Background(16));
+        assert_eq!(mapper.read_nametable(0x03C0, &vram), 0x55);
+        assert_eq!(mapper.read_chr(0x0335), 4);
+
+        mapper.notify_fetch(PpuFetch::Background(40));
+        assert_eq!(mapper.read_nametable(0x0000, &vram), 0);
+    }
+
+    #[test]
+    fn test_irq() {
+        let mut mapper = make_mapper();
+        mapper.write_expansion(0x5203, 2);
+        mapper.write_expansion(0x5204, 0x80);
+
+        mapper.notify_scanline(Some(0));
+        mapper.notify_scanline(Some(1));
+        assert!(!mapper.irq());
+        mapper.notify_scanline(Some(2));
+        assert!(mapper.irq());
+
+        assert_eq!(mapper.read_expansion(0x5204), Some(0xC0));
+        assert!(!mapper.irq());
+
+        mapper.notify_scanline(None);
+        assert_eq!(mapper.read_expansion(0x5204), Some(0));
+    }
+
+    #[test]
+    fn test_multiplier() {
+        let mut mapper = make_mapper();
+        mapper.write_expansion(0x5205, 200);
+        mapper.write_expansion(0x5206, 100);
+        assert_eq!(mapper.read_expansion(0x5205), Some(0x20));
+        assert_eq!(mapper.read_expansion(0x5206), Some(0x4E));
+    }
+
+    #[test]
+    fn test_state() {
+        let mut mapper = make_mapper();
+        mapper.write_expansion(0x5114, 0x81);
+        mapper.write_expansion(0x5101, 3);
+        mapper.write_expansion(0x5128, 20);
+        mapper.write_expansion(0x5104, 2);
+        mapper.write_expansion(0x5C05, 0xC3);
+        mapper.notify_scanline(Some(0));
+        mapper.notify_fetch(PpuFetch::Background(40));
+        let mut w = StateWriter::new();
+        mapper.save_state(&mut w);
+        let data = w.finish();
+
+        let mut other = make_mapper();
+        let mut r = StateReader::new(&data).ok().unwrap();
+        assert!(other.load_state(&mut r).is_ok());
+        assert!(r.finished());
+        assert_eq!(other.read_prg(0x8000), 8);
+        assert_eq!(other.read_chr(0x0000), 20);
+        assert_eq!(other.read_expansion(0x5C05), Some(0xC3));
+        assert_eq!(other.fetch, PpuFetch::Background(40));
+        assert!(other.in_frame);
+    }
+}
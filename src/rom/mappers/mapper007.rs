@@ -0,0 +1,158 @@
+use crate::rom::mapper::Mapper;
+use crate::rom::slice::Slice;
+use crate::rom::ram::Ram;
+use crate::rom::chr::Chr;
+use crate::rom::Mirroring;
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
+const K: u32 = 1024;
+const PRG_BANK_SIZE: u32 = 32 * K;
+
+pub struct Mapper007 {
+    prg_rom: Slice,
+    prg_ram: Ram,
+    chr: Chr,
+    bank: u8,
+    // AMROM and AOROM boards don't prevent the ROM from driving the data bus on writes,
+    // so the written value is ANDed with the ROM content at the same address.
+    bus_conflicts: bool
+}
+
+impl Mapper007 {
+    pub fn new(prg_rom: Slice, chr_rom: Option<Slice>,
+               bus_conflicts: bool, prg_ram_size: u32, chr_ram_size: u32) -> Mapper007 {
+        Mapper007 {
+            prg_rom,
+            prg_ram: Ram::new(prg_ram_size),
+            chr: Chr::new(chr_rom, chr_ram_size),
+            bank: 0,
+            bus_conflicts
+        }
+    }
+
+    fn prg_rom_index(&self, addr: u16) -> u32 {
+        let index = (self.bank & 0x0F) as u32 * PRG_BANK_SIZE + (addr as u32 - 0x8000);
+        index % self.prg_rom.length()
+    }
+}
+
+impl Mapper for Mapper007 {
+    fn read_prg(&mut self, addr: u16) -> u8 {
+        self.prg_rom.at(self.prg_rom_index(addr))
+    }
+
+    fn write_prg(&mut self, addr: u16, value: u8) {
+        self.bank = if self.bus_conflicts {
+            value & self.read_prg(addr)
+        } else {
+            value
+        };
+    }
+
+    fn read_chr(&self, addr: u16) -> u8 {
+        self.chr.at(addr as u32 % self.chr.length())
+    }
+
+    fn write_chr(&mut self, addr: u16, value: u8) {
+        self.chr.set(addr as u32 % self.chr.length(), value);
+    }
+
+    fn prg_ram_mut(&mut self) -> Option<&mut Ram> {
+        Some(&mut self.prg_ram)
+    }
+
+    fn mirroring(&self) -> Option<Mirroring> {
+        if self.bank & 0x10 == 0 {
+            Some(Mirroring::SingleScreenLower)
+        } else {
+            Some(Mirroring::SingleScreenUpper)
+        }
+    }
+}
+
+impl Stateful for Mapper007 {
+    fn save_state(&self, w: &mut StateWriter) {
+        self.prg_ram.save_state(w);
+        self.chr.save_state(w);
+        w.write_u8(self.bank);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        self.prg_ram.load_state(r)?;
+        self.chr.load_state(r)?;
+        self.bank = r.read_u8()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rom::tests::make_slice;
+
+    #[test]
+    fn test_prg_banks() {
+        let mut mapper = Mapper007::new(make_slice(256 * K, PRG_BANK_SIZE), None, false, 0, 8 * K);
+        assert_eq!(mapper.read_prg(0x8000), 0);
+        mapper.write_prg(0x8000, 0x05);
+        assert_eq!(mapper.read_prg(0x8000), 5);
+        assert_eq!(mapper.read_prg(0xFFFE), 5);
+        mapper.write_prg(0xFFFF, 0x0F);
+        assert_eq!(mapper.read_prg(0xC000), 7);
+    }
+
+    #[test]
+    fn test_mirroring() {
+        let mut mapper = Mapper007::new(make_slice(256 * K, PRG_BANK_SIZE), None, false, 0, 8 * K);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::SingleScreenLower));
+        mapper.write_prg(0x8000, 0x13);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::SingleScreenUpper));
+        mapper.write_prg(0x8000, 0x03);
+        assert_eq!(mapper.mirroring(), Some(Mirroring::SingleScreenLower));
+    }
+
+    #[test]
+    fn test_bus_conflicts() {
+        // Every 1K of the bank has a different value to AND with
+        let mut mapper = Mapper007::new(make_slice(256 * K, K), None, true, 0, 8 * K);
+        mapper.write_prg(0xC400, 0x13);
+        assert_eq!(mapper.bank, 0x11);
+        mapper.write_prg(0x8C00, 0x13);
+        assert_eq!(mapper.bank, 0x03);
+
+        let mut mapper = Mapper007::new(make_slice(256 * K, PRG_BANK_SIZE), None, false, 0, 8 * K);
+        mapper.write_prg(0x8000, 0x13);
+        assert_eq!(mapper.bank, 0x13);
+    }
+
+    #[test]
+    fn test_chr_ram() {
+        let mut mapper = Mapper007::new(make_slice(128 * K, PRG_BANK_SIZE), None, false, 0, 8 * K);
+        for addr in [0x0000u16, 0x0123, 0x1000, 0x1FFF] {
+            let v = rand::random::<u8>();
+            mapper.write_chr(addr, v);
+            assert_eq!(mapper.read_chr(addr), v);
+        }
+    }
+
+    #[test]
+    fn test_state() {
+        let mut mapper = Mapper007::new(make_slice(256 * K, PRG_BANK_SIZE), None, false, 8 * K, 8 * K);
+        mapper.write_prg(0x8000, 0x16);
+        mapper.write_ram(0x6000, 0x12);
+        mapper.write_chr(0x0123, 0x34);
+        let mut w = StateWriter::new();
+        mapper.save_state(&mut w);
+        let data = w.finish();
+
+        let mut other = Mapper007::new(make_slice(256 * K, PRG_BANK_SIZE), None, false, 8 * K, 8 * K);
+        let mut r = StateReader::new(&data).ok().unwrap();
+        assert!(other.load_state(&mut r).is_ok());
+        assert!(r.finished());
+        assert_eq!(other.read_prg(0x8000), 6);
+        assert_eq!(other.mirroring(), Some(Mirroring::SingleScreenUpper));
+        assert_eq!(other.read_ram(0x6000), Some(0x12));
+        assert_eq!(other.read_chr(0x0123), 0x34);
+    }
+}
@@ -1,3 +1,15 @@
+mod mapper000;
+mod mapper001;
+mod mapper002;
 mod mapper003;
+mod mapper004;
+mod mapper005;
+mod mapper007;
 
-pub use self::mapper003::Mapper003;
\ No newline at end of file
+pub use self::mapper000::Mapper000;
+pub use self::mapper001::Mapper001;
+pub use self::mapper002::Mapper002;
+pub use self::mapper003::Mapper003;
+pub use self::mapper004::Mapper004;
+pub use self::mapper005::Mapper005;
+pub use self::mapper007::Mapper007;
@@ -0,0 +1,12 @@
+// Nametable mirroring, which is fixed by the header or selected by the mapper
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Mirroring {
+    SingleScreenLower,
+    SingleScreenUpper,
+    Horizontal,
+    Vertical,
+    // The cartridge supplies 2K extra VRAM
+    FourScreen,
+    // The mapper handles every nametable access by itself
+    Mapped
+}
@@ -2,12 +2,17 @@ mod rom;
 mod slice;
 mod mapper;
 mod mappers;
+mod chr;
+mod ram;
+mod mirroring;
 mod timing;
 
 pub use self::rom::Rom;
 pub use self::timing::Timing;
+pub use self::mirroring::Mirroring;
+pub use self::mapper::PpuFetch;
 
 #[cfg(test)]
 pub mod tests {
-    pub use super::rom::tests::mock;
+    pub use super::rom::tests::{mock, make_slice};
 }
\ No newline at end of file
@@ -0,0 +1,84 @@
+use crate::state::{Stateful, StateWriter, StateReader};
+use crate::i18n::Message;
+
+pub struct Ram {
+    data: Vec<u8>,
+    // Whether the RAM has been written since the last check
+    dirty: bool
+}
+
+impl Ram {
+    pub fn new(size: u32) -> Ram {
+        Ram {
+            data: vec![0; size as usize],
+            dirty: false
+        }
+    }
+
+    pub fn length(&self) -> u32 {
+        self.data.len() as u32
+    }
+
+    pub fn at(&self, index: u32) -> u8 {
+        self.data[index as usize]
+    }
+
+    pub fn set(&mut self, index: u32, value: u8) {
+        self.data[index as usize] = value;
+        self.dirty = true;
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    // Copies the data into the RAM, the part out of the RAM size is ignored.
+    pub fn load(&mut self, data: &[u8]) {
+        let len = data.len().min(self.data.len());
+        self.data[..len].copy_from_slice(&data[..len]);
+    }
+
+    pub fn take_dirty(&mut self) -> bool {
+        let dirty = self.dirty;
+        self.dirty = false;
+        dirty
+    }
+}
+
+impl Stateful for Ram {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_bytes(&self.data);
+    }
+
+    // The loaded data is dirty as it may differ from the saved RAM
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        r.read_bytes(&mut self.data)?;
+        self.dirty = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dirty() {
+        let mut ram = Ram::new(16);
+        assert!(!ram.take_dirty());
+        ram.set(3, 0x12);
+        assert!(ram.take_dirty());
+        assert!(!ram.take_dirty());
+
+        ram.load(&[1, 2, 3]);
+        assert!(!ram.take_dirty());
+        assert_eq!(ram.data()[..4], [1, 2, 3, 0x12]);
+    }
+
+    #[test]
+    fn test_load_oversize() {
+        let mut ram = Ram::new(2);
+        ram.load(&[1, 2, 3]);
+        assert_eq!(ram.data(), &[1, 2]);
+    }
+}
@@ -1,8 +1,10 @@
 use crate::i18n::Message;
 use std::rc::Rc;
 use super::slice::Slice;
+use super::ram::Ram;
 use crate::rom::mapper::{find_mapper, Mapper};
-use crate::rom::Timing;
+use crate::rom::{Timing, Mirroring};
+use crate::state::{Stateful, StateWriter, StateReader};
 
 const K: u32 = 1024;
 
@@ -22,7 +24,7 @@ enum Console {
 pub struct Rom {
     prg_rom_size: u32,
     chr_rom_size: u32,
-    mirroring: Option<u8>,
+    mirroring: Mirroring,
     extra_memory: bool,
     console: Console,
     version: Version,
@@ -41,7 +43,7 @@ pub struct Rom {
 }
 
 impl Rom {
-    pub fn parse(data: Box<[u8]>) -> Result<Rom, Message> {
+    pub fn parse(data: Box<[u8]>, bus_conflicts: Option<bool>) -> Result<Rom, Message> {
         let data = Rc::<[u8]>::from(data);
         if data.len() < 16 {
             return Err(Message::MalformedFileFormat)
@@ -53,9 +55,11 @@ impl Rom {
         let mut chr_rom_size = data[5] as u32 * 8 * K;
 
         let mirroring = if data[6] & 0x08 != 0 {
-            None
+            Mirroring::FourScreen
+        } else if data[6] & 0x01 == 0 {
+            Mirroring::Horizontal
         } else {
-            Some(data[6] & 0x01)
+            Mirroring::Vertical
         };
         let extra_memory = data[6] & 0x02 != 0;
         let trainer_exists = data[6] & 0x04 != 0;
@@ -86,10 +90,15 @@ impl Rom {
         let mut exp_device: u8 = 0;
         match version {
             Version::V1 => {
-                prg_ram_size = data[8] as u32 * 8 * K;
+                // Value 0 infers 8K PRG-RAM for compatibility
+                prg_ram_size = data[8].max(1) as u32 * 8 * K;
                 if data[9] & 0x01 > 0 {
                     timing = Timing::PAL;
                 }
+                // Boards without CHR-ROM have 8K CHR-RAM
+                if chr_rom_size == 0 {
+                    chr_ram_size = 8 * K;
+                }
             },
             Version::V2 => {
                 mapper_id |= (data[8] as u16 & 0x0F) << 8;
@@ -150,6 +159,12 @@ impl Rom {
             }
         };
 
+        // A NES 2.0 header without CHR-ROM nor CHR-RAM is taken as 8K CHR-RAM,
+        // since the PPU always has the pattern tables to fetch
+        if chr_rom_size == 0 && chr_ram_size + chr_nv_ram_size == 0 {
+            chr_ram_size = 8 * K;
+        }
+
         let mut start: u32 = 16;
         let trainer = if trainer_exists {
             let s = Some(Slice::new(&data, start, 512));
@@ -163,12 +178,18 @@ impl Rom {
         start += prg_rom_size;
 
         let chr_rom = if chr_rom_size > 0 {
-            Some(Slice::new(&data, start, prg_rom_size))
+            Some(Slice::new(&data, start, chr_rom_size))
         } else {
             None
         };
 
-        let mapper = find_mapper(mapper_id, sub_mapper, prg_rom, chr_rom).unwrap();
+        let mut mapper = find_mapper(mapper_id, sub_mapper, bus_conflicts, prg_rom, chr_rom,
+                                     prg_ram_size + prg_nv_ram_size,
+                                     chr_ram_size + chr_nv_ram_size)
+            .ok_or(Message::UnsupportedMapper(mapper_id))?;
+        if let Some(trainer) = trainer.as_ref() {
+            load_trainer(mapper.as_mut(), trainer);
+        }
 
         Ok(Rom {
             prg_rom_size,
@@ -200,12 +221,59 @@ impl Rom {
         self.mapper.as_mut()
     }
 
+    // The PRG-RAM kept by the battery, None if the cartridge has no battery
+    pub fn battery_ram_mut(&mut self) -> Option<&mut Ram> {
+        if self.extra_memory || self.prg_nv_ram_size > 0 {
+            self.mapper.prg_ram_mut().filter(|ram| ram.length() > 0)
+        } else {
+            None
+        }
+    }
+
+    // The default expansion device in the NES 2.0 header, 0 if unspecified
+    pub fn exp_device(&self) -> u8 {
+        self.exp_device
+    }
+
     pub fn timing(&self) -> &Timing {
         &self.timing
     }
 
-    pub fn mirroring(&self) -> Option<u8> {
-        self.mirroring
+    pub fn mirroring(&self) -> Mirroring {
+        // Four-screen VRAM on the board overrides the mapper
+        if self.mirroring == Mirroring::FourScreen {
+            Mirroring::FourScreen
+        } else {
+            self.mapper.mirroring().unwrap_or(self.mirroring)
+        }
+    }
+}
+
+impl Stateful for Rom {
+    fn save_state(&self, w: &mut StateWriter) {
+        w.write_u32(self.prg_rom_size);
+        w.write_u32(self.chr_rom_size);
+        self.mapper.save_state(w);
+    }
+
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message> {
+        if r.read_u32()? != self.prg_rom_size || r.read_u32()? != self.chr_rom_size {
+            return Err(Message::StateMismatch);
+        }
+        self.mapper.load_state(r)
+    }
+}
+
+// The trainer is loaded into $7000-$71FF of the PRG-RAM
+fn load_trainer(mapper: &mut dyn Mapper, trainer: &Slice) {
+    if let Some(ram) = mapper.prg_ram_mut() {
+        if ram.length() > 0 {
+            for i in 0..trainer.length() {
+                ram.set((0x1000 + i) % ram.length(), trainer.at(i));
+            }
+            // The trainer is a part of the cartridge, not written by the game to be saved
+            ram.take_dirty();
+        }
     }
 }
 
@@ -217,7 +285,7 @@ pub(crate) mod tests {
         Rom {
             prg_rom_size: 0,
             chr_rom_size: 0,
-            mirroring: Some(0),
+            mirroring: Mirroring::Horizontal,
             extra_memory: false,
             console: Console::Nes,
             version: Version::V1,
@@ -235,4 +303,73 @@ pub(crate) mod tests {
             mapper: crate::rom::mapper::tests::mock()
         }
     }
-}
\ No newline at end of file
+
+    // A slice of the size, every block of the block size is filled with its block number
+    pub fn make_slice(size: u32, block: u32) -> Slice {
+        let data: Vec<u8> = (0..size).map(|i| (i / block) as u8).collect();
+        let data = std::rc::Rc::<[u8]>::from(data.into_boxed_slice());
+        Slice::new(&data, 0, size)
+    }
+
+    #[test]
+    fn test_trainer() {
+        let mut data = vec![0u8; 16 + 512 + 16 * K as usize + 8 * K as usize];
+        data[..4].copy_from_slice(&[0x4E, 0x45, 0x53, 0x1A]);
+        data[4] = 1;
+        data[5] = 1;
+        // With the battery
+        data[6] = 0x06;
+        for v in &mut data[16..16 + 512] {
+            *v = 0xAB;
+        }
+
+        let mut rom = Rom::parse(data.into_boxed_slice(), None).ok().unwrap();
+        assert_eq!(rom.mapper_mut().read_ram(0x6FFF), Some(0));
+        assert_eq!(rom.mapper_mut().read_ram(0x7000), Some(0xAB));
+        assert_eq!(rom.mapper_mut().read_ram(0x71FF), Some(0xAB));
+        assert_eq!(rom.mapper_mut().read_ram(0x7200), Some(0));
+        assert!(!rom.battery_ram_mut().unwrap().take_dirty());
+    }
+
+    #[test]
+    fn test_no_chr() {
+        let mut data = vec![0u8; 16 + 16 * K as usize];
+        data[..4].copy_from_slice(&[0x4E, 0x45, 0x53, 0x1A]);
+        data[4] = 1;
+        data[7] = 0x08;
+        let mut rom = Rom::parse(data.into_boxed_slice(), None).ok().unwrap();
+        assert_eq!(rom.chr_ram_size, 8 * K);
+        rom.mapper_mut().write_chr(0x1FFF, 0x56);
+        assert_eq!(rom.mapper().read_chr(0x1FFF), 0x56);
+        assert_eq!(rom.mapper().read_chr(0x0000), 0);
+    }
+
+    #[test]
+    fn test_cnrom_without_chr_rom() {
+        let mut data = vec![0u8; 16 + 32 * K as usize];
+        data[..4].copy_from_slice(&[0x4E, 0x45, 0x53, 0x1A]);
+        data[4] = 2;
+        data[6] = 0x30;
+        let mut rom = Rom::parse(data.into_boxed_slice(), None).ok().unwrap();
+        rom.mapper_mut().write_chr(0x0123, 0x56);
+        assert_eq!(rom.mapper().read_chr(0x0123), 0x56);
+    }
+
+    #[test]
+    fn test_battery_ram() {
+        let mut data = vec![0u8; 16 + 16 * K as usize + 8 * K as usize];
+        data[..4].copy_from_slice(&[0x4E, 0x45, 0x53, 0x1A]);
+        data[4] = 1;
+        data[5] = 1;
+        let mut rom = Rom::parse(data.clone().into_boxed_slice(), None).ok().unwrap();
+        assert!(rom.battery_ram_mut().is_none());
+
+        data[6] = 0x02;
+        let mut rom = Rom::parse(data.into_boxed_slice(), None).ok().unwrap();
+        rom.mapper_mut().write_ram(0x6001, 0x34);
+        let ram = rom.battery_ram_mut().unwrap();
+        assert_eq!(ram.length(), 8 * K);
+        assert_eq!(ram.at(1), 0x34);
+        assert!(ram.take_dirty());
+    }
+}
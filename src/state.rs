@@ -0,0 +1,165 @@
+use crate::i18n::Message;
+
+const MAGIC: [u8; 4] = [0x50, 0x48, 0x53, 0x54];
+
+// The version of the state format, which must be increased
+// whenever any component changes what it saves after the format is released.
+pub const VERSION: u16 = 1;
+
+// The machine components which can be saved into a state and restored from it
+pub trait Stateful {
+    fn save_state(&self, w: &mut StateWriter);
+    fn load_state(&mut self, r: &mut StateReader) -> Result<(), Message>;
+}
+
+pub struct StateWriter {
+    data: Vec<u8>
+}
+
+impl StateWriter {
+    pub fn new() -> Self {
+        let mut w = Self {
+            data: Vec::new()
+        };
+        w.data.extend_from_slice(&MAGIC);
+        w.write_u16(VERSION);
+        w
+    }
+
+    pub fn write_u8(&mut self, v: u8) {
+        self.data.push(v);
+    }
+
+    pub fn write_u16(&mut self, v: u16) {
+        self.data.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, v: u32) {
+        self.data.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub fn write_bool(&mut self, v: bool) {
+        self.write_u8(v as u8);
+    }
+
+    // Blocks are prefixed by the length, which is checked when loaded
+    pub fn write_bytes(&mut self, v: &[u8]) {
+        self.write_u32(v.len() as u32);
+        self.data.extend_from_slice(v);
+    }
+
+    pub fn finish(self) -> Box<[u8]> {
+        self.data.into_boxed_slice()
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> Result<Self, Message> {
+        if data.len() < MAGIC.len() || data[..MAGIC.len()] != MAGIC {
+            return Err(Message::MalformedState);
+        }
+        let mut r = Self {
+            data,
+            pos: MAGIC.len()
+        };
+        let version = r.read_u16()?;
+        if version != VERSION {
+            return Err(Message::UnsupportedStateVersion(version));
+        }
+        Ok(r)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Message> {
+        if self.pos + len > self.data.len() {
+            return Err(Message::MalformedState);
+        }
+        let s = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(s)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, Message> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, Message> {
+        let s = self.take(2)?;
+        Ok(u16::from_le_bytes([s[0], s[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, Message> {
+        let s = self.take(4)?;
+        Ok(u32::from_le_bytes([s[0], s[1], s[2], s[3]]))
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, Message> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    // Fills the whole buffer, the saved block must have the same length
+    pub fn read_bytes(&mut self, v: &mut [u8]) -> Result<(), Message> {
+        if self.read_u32()? as usize != v.len() {
+            return Err(Message::MalformedState);
+        }
+        v.copy_from_slice(self.take(v.len())?);
+        Ok(())
+    }
+
+    pub fn finished(&self) -> bool {
+        self.pos == self.data.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut w = StateWriter::new();
+        w.write_u8(0x12);
+        w.write_u16(0x3456);
+        w.write_u32(0x789ABCDE);
+        w.write_bool(true);
+        w.write_bytes(&[1, 2, 3]);
+        let data = w.finish();
+
+        let mut r = StateReader::new(&data).ok().unwrap();
+        assert_eq!(r.read_u8().ok(), Some(0x12));
+        assert_eq!(r.read_u16().ok(), Some(0x3456));
+        assert_eq!(r.read_u32().ok(), Some(0x789ABCDE));
+        assert_eq!(r.read_bool().ok(), Some(true));
+        let mut bytes = [0u8; 3];
+        assert!(r.read_bytes(&mut bytes).is_ok());
+        assert_eq!(bytes, [1, 2, 3]);
+        assert!(r.finished());
+        assert!(r.read_u8().is_err());
+    }
+
+    #[test]
+    fn test_header() {
+        assert!(StateReader::new(&[]).is_err());
+        assert!(StateReader::new(&[0x50, 0x48, 0x53, 0x00, 0x01, 0x00]).is_err());
+
+        let mut data = StateWriter::new().finish();
+        assert!(StateReader::new(&data).is_ok());
+        data[4] = data[4].wrapping_add(1);
+        assert!(StateReader::new(&data).is_err());
+    }
+
+    #[test]
+    fn test_bytes_length() {
+        let mut w = StateWriter::new();
+        w.write_bytes(&[1, 2, 3]);
+        let data = w.finish();
+
+        let mut r = StateReader::new(&data).ok().unwrap();
+        let mut bytes = [0u8; 4];
+        assert!(r.read_bytes(&mut bytes).is_err());
+    }
+}